@@ -1,20 +1,28 @@
 use std::fs::{File};
-use std::io::{BufRead, BufReader, ErrorKind, Error};
+use std::io::{self, BufRead, BufReader, ErrorKind, Error, Write};
 
 struct ExpenseData {
-    expenses: [bool; 2021],
+    sorted: Vec<usize>,
+    /// `prefix[i]` is the sum of the `i` smallest entries, so any run of
+    /// sorted entries can be totalled at once.
+    prefix: Vec<u128>,
     min: usize,
     max: usize,
 }
 
 impl ExpenseData {
+    fn new() -> ExpenseData {
+        ExpenseData {
+            sorted: vec![],
+            prefix: vec![],
+            min: usize::MAX,
+            max: 0,
+        }
+    }
+
     fn read(path: &str) -> std::io::Result<ExpenseData> {
         let file = File::open(path)?;
-        let mut result = ExpenseData {
-            expenses: [false; 2021],
-            min: 2021,
-            max: 0,
-        };
+        let mut result = ExpenseData::new();
 
         let mut reader = BufReader::new(file);
 
@@ -27,54 +35,253 @@ impl ExpenseData {
             }
 
             match line.trim_end().parse::<usize>() {
-                Ok(idx) => {
-                    result.expenses[idx] = true;
-                    if idx < result.min {
-                        result.min = idx;
-                    }
-
-                    if idx > result.max {
-                        result.max = idx;
-                    }
-                },
-                Err(_) => return Err(Error::new(ErrorKind::Other, "Failed to parse digit")),
+                Ok(idx) => result.add(idx),
+                Err(_) => return Err(Error::other("Failed to parse digit")),
             }
         }
 
+        if result.sorted.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidData, "No expenses found"));
+        }
+
+        result.build_index();
         Ok(result)
     }
 
+    /// Sorts the entries and totals them for the searches.
+    fn build_index(&mut self) {
+        self.sorted.sort_unstable();
+        self.prefix = std::iter::once(0)
+            .chain(self.sorted.iter().scan(0u128, |total, &value| {
+                *total += value as u128;
+                Some(*total)
+            }))
+            .collect();
+    }
+
+    /// The sum of the sorted entries in `range`.
+    fn total(&self, range: std::ops::Range<usize>) -> u128 {
+        self.prefix[range.end] - self.prefix[range.start]
+    }
+
+    fn add(&mut self, idx: usize) {
+        self.sorted.push(idx);
+
+        if idx < self.min {
+            self.min = idx;
+        }
+
+        if idx > self.max {
+            self.max = idx;
+        }
+    }
+
     fn find_match_to_amount(&self, amt: usize) -> (usize, usize) {
-        for idx in self.min..=self.max {
-            if self.expenses[idx] {
-                if amt >= idx {
-                    if self.expenses[amt-idx] {
-                        return (idx, amt-idx);
-                    }
-                }
-            }
+        match self.find_k_way(2, amt) {
+            Some(found) => (found[0], found[1]),
+            None => (0, 0),
         }
-        (0, 0)
     }
 
     fn find_threeway(&self, amt: usize) -> (usize, usize, usize) {
-        for idx in self.min..=self.max {
-            if self.expenses[idx] {
-                let result = amt - idx;
+        match self.find_k_way(3, amt) {
+            Some(found) => (found[0], found[1], found[2]),
+            None => (0, 0, 0),
+        }
+    }
 
-                let candidate = self.find_match_to_amount(result);
-                if candidate != (0, 0) {
-                    return (idx, candidate.0, candidate.1);
-                }
+    /// Finds `k` distinct entries of the report that sum to `amt`, smallest
+    /// first.  Walks the sorted index built at load time, so each query is
+    /// a search over the entries rather than a re-read of the file.
+    fn find_k_way(&self, k: usize, amt: usize) -> Option<Vec<usize>> {
+        if k == 0 || k > self.sorted.len() {
+            return None;
+        }
+
+        let mut chosen = Vec::with_capacity(k);
+        if self.search(0, k, amt, &mut chosen) {
+            Some(chosen)
+        } else {
+            None
+        }
+    }
+
+    fn search(&self, start: usize, k: usize, amt: usize, chosen: &mut Vec<usize>) -> bool {
+        if k == 1 {
+            // The remainder must be one of the entries we have not used yet.
+            if self.sorted[start..].binary_search(&amt).is_ok() {
+                chosen.push(amt);
+                return true;
+            }
+            return false;
+        }
+
+        let len = self.sorted.len();
+        let mut idx = start;
+        while idx + k <= len {
+            let value = self.sorted[idx];
+            // The k entries from here are the smallest left, so once they
+            // overshoot the target nothing further along can match.
+            if self.total(idx..idx + k) > amt as u128 {
+                break;
+            }
+            // With the k - 1 largest entries this value still falls short,
+            // so only a larger value can work.
+            if value as u128 + self.total(len - (k - 1)..len) < amt as u128 {
+                idx += 1;
+                continue;
+            }
+
+            chosen.push(value);
+            if self.search(idx + 1, k - 1, amt - value, chosen) {
+                return true;
+            }
+            chosen.pop();
+
+            // Skip duplicates of a value we have already tried as this term.
+            while idx < self.sorted.len() && self.sorted[idx] == value {
+                idx += 1;
+            }
+        }
+        false
+    }
+
+    fn stats(&self) -> Stats {
+        Stats {
+            count: self.sorted.len(),
+            distinct: self.sorted.len() - self.sorted.windows(2).filter(|pair| pair[0] == pair[1]).count(),
+            min: self.min,
+            max: self.max,
+            sum: self.sorted.iter().try_fold(0usize, |acc, &value| acc.checked_add(value)),
+            mean: self.sorted.iter().map(|&value| value as f64).sum::<f64>() / self.sorted.len() as f64,
+        }
+    }
+}
+
+struct Stats {
+    count: usize,
+    distinct: usize,
+    min: usize,
+    max: usize,
+    /// `None` when the total doesn't fit in a `usize`.
+    sum: Option<usize>,
+    mean: f64,
+}
+
+/// A single command typed at the interactive prompt.
+enum Query {
+    Pair(usize),
+    Triple(usize),
+    KWay(usize, usize),
+    Stats,
+    Help,
+    Quit,
+}
+
+impl Query {
+    fn parse(line: &str) -> Result<Query, String> {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        let number = |idx: usize| -> Result<usize, String> {
+            parts.get(idx)
+                .ok_or_else(|| format!("'{}' needs more arguments", parts[0]))?
+                .parse::<usize>()
+                .map_err(|_| format!("'{}' is not a number", parts[idx]))
+        };
+
+        let query = match parts.first() {
+            Some(&"pair") => Query::Pair(number(1)?),
+            Some(&"triple") => Query::Triple(number(1)?),
+            Some(&"k") => Query::KWay(number(1)?, number(2)?),
+            Some(&"stats") => Query::Stats,
+            Some(&"help") => Query::Help,
+            Some(&"quit") | Some(&"exit") => Query::Quit,
+            Some(other) => return Err(format!("Unknown command '{}', try 'help'", other)),
+            None => return Err(String::from("Empty command")),
+        };
+
+        let expected = match query {
+            Query::Pair(_) | Query::Triple(_) => 2,
+            Query::KWay(_, _) => 3,
+            _ => 1,
+        };
+        if parts.len() > expected {
+            return Err(format!("Too many arguments for '{}'", parts[0]));
+        }
+
+        Ok(query)
+    }
+}
+
+fn print_k_way(data: &ExpenseData, k: usize, amt: usize) {
+    match data.find_k_way(k, amt) {
+        Some(found) => {
+            let product = found.iter().try_fold(1usize, |acc, &value| acc.checked_mul(value));
+            let terms: Vec<String> = found.iter().map(|value| value.to_string()).collect();
+            match product {
+                Some(product) => println!("Found {} = {} (product {})", terms.join(" + "), amt, product),
+                None => println!("Found {} = {} (product overflows)", terms.join(" + "), amt),
             }
+        },
+        None => println!("No {} entries sum to {}", k, amt),
+    }
+}
+
+fn interactive(data: &ExpenseData) -> io::Result<()> {
+    let stdin = io::stdin();
+    let mut stdout = io::stdout();
+
+    loop {
+        print!("> ");
+        stdout.flush()?;
+
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        match Query::parse(&line) {
+            Ok(Query::Pair(amt)) => print_k_way(data, 2, amt),
+            Ok(Query::Triple(amt)) => print_k_way(data, 3, amt),
+            Ok(Query::KWay(k, amt)) => print_k_way(data, k, amt),
+            Ok(Query::Stats) => {
+                let stats = data.stats();
+                let sum = stats.sum.map_or(String::from("overflows"), |sum| sum.to_string());
+                println!("{} entries ({} distinct), min {}, max {}, sum {}, mean {:.2}",
+                    stats.count, stats.distinct, stats.min, stats.max, sum, stats.mean);
+            },
+            Ok(Query::Help) => {
+                println!("pair <amount>        two entries summing to amount");
+                println!("triple <amount>      three entries summing to amount");
+                println!("k <count> <amount>   count entries summing to amount");
+                println!("stats                summary of the loaded report");
+                println!("quit                 leave");
+            },
+            Ok(Query::Quit) => return Ok(()),
+            Err(message) => println!("{}", message),
         }
-        (0, 0, 0)
     }
 }
 
 fn main() {
-    match ExpenseData::read("./expense_input.txt") {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let interactive_mode = args.iter().any(|arg| arg == "-i" || arg == "--interactive");
+    let path = args.iter()
+        .find(|arg| !arg.starts_with('-'))
+        .map(|arg| arg.as_str())
+        .unwrap_or("./expense_input.txt");
+
+    match ExpenseData::read(path) {
         Ok(data) => {
+            if interactive_mode {
+                if let Err(error) = interactive(&data) {
+                    println!("Yah basic! {}", error);
+                }
+                return;
+            }
+
             let matched = data.find_match_to_amount(2020);
             println!("Found {} x {} = {}", matched.0, matched.1, matched.0 * matched.1);
 
@@ -84,3 +291,75 @@ fn main() {
         Err(error) => println!("Yah basic! {}", error),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expenses(values: &[usize]) -> ExpenseData {
+        let mut data = ExpenseData::new();
+        for &value in values {
+            data.add(value);
+        }
+        data.build_index();
+        data
+    }
+
+    #[test]
+    fn check_find_k_way() {
+        let data = expenses(&[1721, 979, 366, 299, 675, 1456]);
+        assert_eq!(data.find_k_way(2, 2020), Some(vec![299, 1721]));
+        assert_eq!(data.find_k_way(3, 2020), Some(vec![366, 675, 979]));
+        assert_eq!(data.find_k_way(2, 7), None);
+        assert_eq!(data.find_k_way(0, 2020), None);
+        assert_eq!(data.find_k_way(7, 2020), None);
+
+        // An entry only pairs with itself if the report lists it twice.
+        assert_eq!(expenses(&[1010, 5]).find_k_way(2, 2020), None);
+        assert_eq!(expenses(&[5, 1010, 1010]).find_k_way(2, 2020), Some(vec![1010, 1010]));
+        assert_eq!(expenses(&[1, 1, 1]).find_k_way(3, 3), Some(vec![1, 1, 1]));
+        assert_eq!(expenses(&[1, 1]).find_k_way(3, 3), None);
+
+        // Targets out of reach return at once rather than trying every combination.
+        let many: Vec<usize> = (1..=200).map(|value| value * 7 % 2003).collect();
+        let data = expenses(&many);
+        assert_eq!(data.find_k_way(20, 100_000), None);
+        assert_eq!(data.find_k_way(5, 100_000), None);
+        let largest: usize = data.sorted[data.sorted.len() - 20..].iter().sum();
+        assert_eq!(data.find_k_way(20, largest).map(|found| found.iter().sum::<usize>()), Some(largest));
+        assert_eq!(data.find_k_way(20, largest + 1), None);
+    }
+
+    #[test]
+    fn check_stats() {
+        let stats = expenses(&[4, 2, 4, 9]).stats();
+        assert_eq!((stats.count, stats.distinct, stats.min, stats.max, stats.sum), (4, 3, 2, 9, Some(19)));
+        assert!((stats.mean - 4.75).abs() < 1e-9);
+
+        let huge = expenses(&[usize::MAX, 1, 4_000_000_000]);
+        assert_eq!(huge.stats().sum, None);
+        assert_eq!(huge.find_k_way(2, usize::MAX), None);
+        assert_eq!(huge.find_k_way(1, 4_000_000_000), Some(vec![4_000_000_000]));
+    }
+
+    #[test]
+    fn check_query_parse() {
+        assert!(matches!(Query::parse("pair 2020"), Ok(Query::Pair(2020))));
+        assert!(matches!(Query::parse("  k 4 100 "), Ok(Query::KWay(4, 100))));
+        assert!(matches!(Query::parse("exit"), Ok(Query::Quit)));
+
+        let errors = [
+            ("", "Empty command"),
+            ("pair", "'pair' needs more arguments"),
+            ("k 3", "'k' needs more arguments"),
+            ("triple ten", "'ten' is not a number"),
+            ("pair -1", "'-1' is not a number"),
+            ("pair 1 2", "Too many arguments for 'pair'"),
+            ("stats now", "Too many arguments for 'stats'"),
+            ("sum 3", "Unknown command 'sum', try 'help'"),
+        ];
+        for (line, message) in errors.iter() {
+            assert_eq!(Query::parse(line).err().as_deref(), Some(*message), "{}", line);
+        }
+    }
+}
@@ -0,0 +1,312 @@
+use crate::rules::Rule;
+use crate::{Example, Policy, PolicyKind};
+
+/// SplitMix64: small, fast and the same on every platform, which is all a
+/// reproducible test-data generator needs.
+struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Rng { state: seed }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `0..bound`.
+    fn below(&mut self, bound: usize) -> usize {
+        (self.next_u64() % bound as u64) as usize
+    }
+}
+
+/// The longest password the generator builds.  Policies that can only be
+/// met or broken by longer ones get no sample rather than a huge string.
+const LONGEST: usize = 4096;
+
+/// A password that breaks a policy, and which way it breaks it.
+pub struct Violation {
+    pub description: String,
+    pub password: String,
+}
+
+/// Makes sample passwords for testing validators.  The same seed always
+/// produces the same passwords.
+pub struct Generator {
+    rng: Rng,
+}
+
+impl Generator {
+    pub fn new(seed: u64) -> Self {
+        Generator { rng: Rng::new(seed) }
+    }
+
+    /// Letters that can pad a password without matching the policy.
+    fn filler(policy: &Policy) -> Vec<String> {
+        ('a'..='z')
+            .map(|c| c.to_string())
+            .filter(|c| !policy.matches(c) && !policy.matches(&c.to_uppercase()))
+            .collect()
+    }
+
+    /// A random password that satisfies the policy, or `None` if nothing
+    /// can (a Toboggan policy naming the same position twice) or it would
+    /// be longer than `LONGEST`.
+    pub fn satisfying(&mut self, policy: &Policy) -> Option<String> {
+        if policy.validate().is_err() || policy.min as usize > LONGEST {
+            return None;
+        }
+
+        let filler = Generator::filler(policy);
+        match policy.kind {
+            PolicyKind::SledRental => {
+                // Keep the count near the minimum so huge maximums stay cheap.
+                let span = (policy.max - policy.min).min(8) as usize;
+                let count = policy.min as usize + self.rng.below(span + 1);
+                let length = (count + self.rng.below(6)).max(1);
+
+                let mut password: Vec<String> = (0..length)
+                    .map(|_| filler[self.rng.below(filler.len())].clone())
+                    .collect();
+                let mut slots: Vec<usize> = (0..length).collect();
+                for _ in 0..count {
+                    let slot = slots.swap_remove(self.rng.below(slots.len()));
+                    password[slot] = policy.character.clone();
+                }
+                Some(password.concat())
+            },
+            PolicyKind::Toboggan => {
+                if policy.min == policy.max || policy.max as usize > LONGEST {
+                    return None;
+                }
+
+                let (first, second) = (policy.min as usize - 1, policy.max as usize - 1);
+                let length = policy.max as usize + self.rng.below(4);
+                let mut password: Vec<String> = (0..length)
+                    .map(|idx| {
+                        // The character may turn up anywhere but the two checked positions.
+                        if idx != first && idx != second && self.rng.below(4) == 0 {
+                            policy.character.clone()
+                        } else {
+                            filler[self.rng.below(filler.len())].clone()
+                        }
+                    })
+                    .collect();
+                let chosen = if self.rng.below(2) == 0 { first } else { second };
+                password[chosen] = policy.character.clone();
+                Some(password.concat())
+            },
+        }
+    }
+
+    /// The shortest password breaking the policy in each way it can be
+    /// broken, leaving out any longer than `LONGEST`.  An invalid policy
+    /// has no violations to show.
+    pub fn violations(policy: &Policy) -> Vec<Violation> {
+        let mut result: Vec<Violation> = vec![];
+        if policy.validate().is_err() {
+            return result;
+        }
+
+        let filler = &Generator::filler(policy)[0];
+        let repeat = |text: &str, count: usize| text.repeat(count);
+        let (min, max) = (policy.min as usize, policy.max as usize);
+
+        match policy.kind {
+            PolicyKind::SledRental => {
+                if min > 0 && min - 1 <= LONGEST {
+                    let count = min - 1;
+                    // A password needs at least one character, so pad an empty one.
+                    let password = if count == 0 { filler.clone() } else { repeat(&policy.character, count) };
+                    result.push(Violation {
+                        description: format!("'{}' appears {} times, below min {}", policy.character, count, policy.min),
+                        password,
+                    });
+                }
+                if max < LONGEST {
+                    result.push(Violation {
+                        description: format!("'{}' appears {} times, above max {}", policy.character, max + 1, policy.max),
+                        password: repeat(&policy.character, max + 1),
+                    });
+                }
+            },
+            PolicyKind::Toboggan if min == max => {
+                // The same position can't match once and not match, so everything fails.
+                result.push(Violation {
+                    description: format!("position {} is checked twice, so no password passes", policy.min),
+                    password: filler.clone(),
+                });
+            },
+            PolicyKind::Toboggan => {
+                if max <= LONGEST {
+                    let mut both: Vec<String> = vec![filler.clone(); max];
+                    both[min - 1] = policy.character.clone();
+                    both[max - 1] = policy.character.clone();
+                    result.push(Violation {
+                        description: format!("positions {} and {} both match", policy.min, policy.max),
+                        password: both.concat(),
+                    });
+                    result.push(Violation {
+                        description: format!("neither position {} nor {} matches", policy.min, policy.max),
+                        password: repeat(filler, max),
+                    });
+                }
+                if min > 1 && min - 1 <= LONGEST {
+                    result.push(Violation {
+                        description: format!("password ends before position {}", policy.min),
+                        password: repeat(filler, min - 1),
+                    });
+                }
+            },
+        }
+
+        result
+    }
+
+    /// A random password passing every policy on the example.  Candidates
+    /// are built to satisfy one of its puzzle policies and kept only if the
+    /// whole example passes, so this gives up after `attempts` tries.
+    pub fn satisfying_example(&mut self, example: &Example, attempts: usize) -> Option<String> {
+        let policies = puzzle_policies(&example.policies);
+        for _ in 0..attempts {
+            let candidate = if policies.is_empty() {
+                let length = 1 + self.rng.below(16);
+                (0..length).map(|_| (b'!' + self.rng.below(94) as u8) as char).collect()
+            } else {
+                let policy = policies[self.rng.below(policies.len())];
+                match self.satisfying(policy) {
+                    Some(candidate) => candidate,
+                    None => continue,
+                }
+            };
+
+            let mut trial = Example::new(&candidate);
+            trial.policies = example.policies.clone();
+            if trial.passes() {
+                return Some(candidate);
+            }
+        }
+        None
+    }
+
+    /// Violations of each puzzle policy the example must satisfy.  Every
+    /// password returned fails the example as a whole.
+    pub fn example_violations(example: &Example) -> Vec<Violation> {
+        let mut result: Vec<Violation> = vec![];
+        for policy in puzzle_policies(&example.policies) {
+            for violation in Generator::violations(policy) {
+                let mut trial = Example::new(&violation.password);
+                trial.policies = example.policies.clone();
+                if !trial.passes() {
+                    result.push(Violation {
+                        description: format!("{}: {}", policy, violation.description),
+                        password: violation.password,
+                    });
+                }
+            }
+        }
+        result
+    }
+}
+
+/// The puzzle policies an example requires, looking inside `and` groups.
+fn puzzle_policies(rules: &[Rule]) -> Vec<&Policy> {
+    let mut result: Vec<&Policy> = vec![];
+    for rule in rules {
+        match rule {
+            Rule::Policy(policy) => result.push(policy),
+            Rule::And(inner) => result.extend(puzzle_policies(inner)),
+            _ => {},
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_generated_passwords() {
+        let policies = vec![
+            Policy::new(PolicyKind::SledRental, 1, 3, 'a'),
+            Policy::new(PolicyKind::SledRental, 0, 2, 'z'),
+            Policy::new(PolicyKind::Toboggan, 1, 3, 'b'),
+            Policy::new(PolicyKind::Toboggan, 4, 9, 'é'),
+        ];
+
+        let mut generator = Generator::new(2020);
+        for policy in &policies {
+            for _ in 0..50 {
+                let password = generator.satisfying(policy).expect("Should be satisfiable");
+                assert!(policy.evalute(&password), "{} should pass {}", password, policy);
+            }
+            for violation in Generator::violations(policy) {
+                assert!(!policy.evalute(&violation.password), "{} should fail {}", violation.password, policy);
+            }
+        }
+
+        let first: Vec<Option<String>> = policies.iter().map(|policy| Generator::new(7).satisfying(policy)).collect();
+        let second: Vec<Option<String>> = policies.iter().map(|policy| Generator::new(7).satisfying(policy)).collect();
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn check_awkward_policies() {
+        let same = Policy::new(PolicyKind::Toboggan, 3, 3, 'b');
+        assert_eq!(Generator::new(1).satisfying(&same), None);
+        let violations = Generator::violations(&same);
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].description, "position 3 is checked twice, so no password passes");
+        assert!(!same.evalute(&violations[0].password));
+
+        // Position 0 doesn't exist, so there is nothing to generate.
+        let zero = Policy::new(PolicyKind::Toboggan, 0, 2, 'b');
+        assert_eq!(Generator::new(1).satisfying(&zero), None);
+        assert!(Generator::violations(&zero).is_empty());
+
+        // Counts this big would need gigabytes of password.
+        let huge = Policy::new(PolicyKind::SledRental, 1_000_000_000, 1_000_000_001, 'a');
+        assert_eq!(Generator::new(1).satisfying(&huge), None);
+        assert!(Generator::violations(&huge).is_empty());
+
+        let far = Policy::new(PolicyKind::Toboggan, 2, 1_000_000_000, 'a');
+        assert_eq!(Generator::new(1).satisfying(&far), None);
+        let descriptions: Vec<String> = Generator::violations(&far).into_iter()
+            .map(|violation| violation.description)
+            .collect();
+        assert_eq!(descriptions, vec!["password ends before position 2"]);
+
+        let wide = Policy::new(PolicyKind::SledRental, 2, 1_000_000_000, 'a');
+        assert!(wide.evalute(&Generator::new(1).satisfying(&wide).unwrap()));
+        assert_eq!(Generator::violations(&wide).len(), 1);
+    }
+
+    #[test]
+    fn check_generated_examples() {
+        let mut example = Example::new("");
+        example.add_policy(Rule::parse("sled 2-4 x and toboggan 1-2 x and length 3+").unwrap());
+
+        let mut generator = Generator::new(1);
+        let password = generator.satisfying_example(&example, 1000).expect("Should find a password");
+        let mut trial = Example::new(&password);
+        trial.policies = example.policies.clone();
+        assert!(trial.passes());
+
+        let descriptions: Vec<String> = Generator::example_violations(&example).into_iter()
+            .map(|violation| violation.description)
+            .collect();
+        assert_eq!(descriptions, vec![
+            "sled 2-4 'x': 'x' appears 1 times, below min 2",
+            "sled 2-4 'x': 'x' appears 5 times, above max 4",
+            "toboggan 1-2 'x': positions 1 and 2 both match",
+            "toboggan 1-2 'x': neither position 1 nor 2 matches",
+        ]);
+    }
+}
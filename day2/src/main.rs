@@ -1,87 +1,342 @@
+use std::convert::TryFrom;
+use std::fmt;
 use std::io::{prelude::*, BufReader};
-use regex;
+use std::str::FromStr;
+use unicode_segmentation::UnicodeSegmentation;
 
-#[derive(Clone, Copy)]
+mod generator;
+mod rules;
+mod stream;
+
+use generator::Generator;
+use rules::Rule;
+use stream::AuditOptions;
+
+/// How the two numbers in a policy are interpreted.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum PolicyKind {
+    /// The sled rental rule: the character appears between min and max times.
+    SledRental,
+    /// The Toboggan rule: exactly one of the two 1-based positions holds the character.
+    Toboggan,
+}
+
+impl FromStr for PolicyKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sled" | "count" => Ok(PolicyKind::SledRental),
+            "toboggan" | "position" => Ok(PolicyKind::Toboggan),
+            _ => Err(format!("Unknown policy kind '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for PolicyKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PolicyKind::SledRental => f.write_str("sled"),
+            PolicyKind::Toboggan => f.write_str("toboggan"),
+        }
+    }
+}
+
+/// The outcome of checking one policy against a password, with the reason
+/// spelled out.
+struct Verdict {
+    policy: String,
+    passed: bool,
+    reason: String,
+}
+
+/// Every policy's verdict for one password.
+struct Evaluation {
+    verdicts: Vec<Verdict>,
+}
+
+impl Evaluation {
+    fn passed(&self) -> bool {
+        self.verdicts.iter().all(|verdict| verdict.passed)
+    }
+
+    fn failures(&self) -> impl Iterator<Item = &Verdict> {
+        self.verdicts.iter().filter(|verdict| !verdict.passed)
+    }
+}
+
+/// A single puzzle policy.  The character is one user-visible character
+/// (a grapheme cluster), and positions count grapheme clusters, so `é`
+/// written as `e` plus a combining accent is still one position.
+#[derive(Clone)]
 struct Policy {
+    kind: PolicyKind,
     min: i32,
     max: i32,
-    character: char,
+    character: String,
+    ignore_case: bool,
 }
 
 impl Policy {
-    fn new(min: i32, max: i32, character: char) -> Self {
+    fn new<S: Into<String>>(kind: PolicyKind, min: i32, max: i32, character: S) -> Self {
         Policy {
+            kind,
             min,
             max,
-            character
+            character: character.into(),
+            ignore_case: false,
+        }
+    }
+
+    fn matches(&self, grapheme: &str) -> bool {
+        if self.ignore_case {
+            grapheme.to_lowercase() == self.character.to_lowercase()
+        } else {
+            grapheme == self.character
         }
     }
 
+    /// The grapheme at a 1-based position, if the password is long enough.
+    fn grapheme_at(test_string: &str, position: i32) -> Option<&str> {
+        let idx = usize::try_from(position - 1).ok()?;
+        test_string.graphemes(true).nth(idx)
+    }
+
     fn evalute(&self, test_string: &str) -> bool {
-        //let mut count = 0;
-        //for c in test_string.chars() {
-        //    if c == self.character {
-        //        count += 1;
-        //    }
-        //}
-        //count <= self.max && count >= self.min
-        let first_character = test_string.chars().nth((self.min - 1) as usize).unwrap_or_default();
-        let second_character = test_string.chars().nth((self.max - 1) as usize).unwrap_or_default();
+        match self.kind {
+            PolicyKind::SledRental => {
+                let count = test_string.graphemes(true).filter(|g| self.matches(g)).count() as i32;
+                count <= self.max && count >= self.min
+            },
+            PolicyKind::Toboggan => {
+                let first = Policy::grapheme_at(test_string, self.min).is_some_and(|g| self.matches(g));
+                let second = Policy::grapheme_at(test_string, self.max).is_some_and(|g| self.matches(g));
+
+                first != second
+            },
+        }
+    }
+
+    /// Checks the numbers make sense for the kind of policy.
+    fn validate(&self) -> Result<(), String> {
+        if self.character.graphemes(true).count() != 1 {
+            return Err(format!("'{}' is not a single character", self.character));
+        }
+        if self.min > self.max {
+            return Err(format!("min {} is above max {}", self.min, self.max));
+        }
+        if self.kind == PolicyKind::Toboggan && self.min < 1 {
+            return Err(format!("positions start at 1, found {}", self.min));
+        }
+        if self.min < 0 {
+            return Err(format!("count cannot be negative, found {}", self.min));
+        }
+        Ok(())
+    }
+
+    fn explain(&self, test_string: &str) -> Verdict {
+        let reason = match self.kind {
+            PolicyKind::SledRental => {
+                let count = test_string.graphemes(true).filter(|g| self.matches(g)).count();
+                format!("'{}' appears {} times, expected {}-{}", self.character, count, self.min, self.max)
+            },
+            PolicyKind::Toboggan => {
+                let describe = |position: i32| {
+                    match Policy::grapheme_at(test_string, position) {
+                        Some(g) => format!("position {} is '{}'", position, g),
+                        None => format!("position {} is past the end", position),
+                    }
+                };
+                let first = Policy::grapheme_at(test_string, self.min).is_some_and(|g| self.matches(g));
+                let second = Policy::grapheme_at(test_string, self.max).is_some_and(|g| self.matches(g));
+                let outcome = match (first, second) {
+                    (true, true) => String::from("both match"),
+                    (false, false) => String::from("neither matches"),
+                    (true, false) => format!("only position {} matches", self.min),
+                    (false, true) => format!("only position {} matches", self.max),
+                };
+                format!("{}, {}: {}", describe(self.min), describe(self.max), outcome)
+            },
+        };
 
-        (first_character == self.character || second_character == self.character) && !(first_character == self.character && second_character == self.character)
+        Verdict {
+            policy: self.to_string(),
+            passed: self.evalute(test_string),
+            reason,
+        }
+    }
+}
+
+impl fmt::Display for Policy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}-{} '{}'", self.kind, self.min, self.max, self.character)?;
+        if self.ignore_case {
+            f.write_str(" nocase")?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses one `[kind] min-max c: password` line of the database.
+struct LineParser {
+    pattern: regex::Regex,
+    default_kind: PolicyKind,
+    ignore_case: bool,
+}
+
+impl LineParser {
+    fn new(default_kind: PolicyKind, ignore_case: bool) -> Self {
+        LineParser {
+            pattern: regex::Regex::new("^(?:([a-z]+)\\s+)?(\\d+)-(\\d+)\\s+(\\S+?)\\s*:\\s*(\\S.*)$").expect("Should have a legit RE"),
+            default_kind,
+            ignore_case,
+        }
+    }
+
+    fn parse(&self, line: &str) -> Result<Example, String> {
+        let capture = self.pattern.captures(line.trim())
+            .ok_or_else(|| String::from("expected '[kind] min-max c: password'"))?;
+
+        let kind = match capture.get(1) {
+            Some(prefix) => prefix.as_str().parse()?,
+            None => self.default_kind,
+        };
+        let min = capture[2].parse().map_err(|_| format!("{} is too large", &capture[2]))?;
+        let max = capture[3].parse().map_err(|_| format!("{} is too large", &capture[3]))?;
+        let password = &capture[5];
+
+        let mut policy = Policy::new(kind, min, max, &capture[4]);
+        policy.ignore_case = self.ignore_case;
+        policy.validate()?;
+
+        let mut example = Example::new(password);
+        example.add_policy(policy);
+        Ok(example)
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ParseMode {
+    Strict,
+    Lenient,
+}
+
+/// A line of the database that could not be turned into an example.
+#[derive(Debug)]
+struct ParseIssue {
+    line: usize,
+    text: String,
+    reason: String,
+}
+
+impl fmt::Display for ParseIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {} ({})", self.line, self.reason, self.text.trim())
+    }
+}
+
+#[derive(Debug)]
+enum DatabaseError {
+    Io(std::io::Error),
+    Malformed(Vec<ParseIssue>),
+}
+
+impl fmt::Display for DatabaseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DatabaseError::Io(error) => write!(f, "Failed to read database: {}", error),
+            DatabaseError::Malformed(issues) => {
+                write!(f, "{} malformed lines", issues.len())?;
+                for issue in issues {
+                    write!(f, "\n    {}", issue)?;
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+impl From<std::io::Error> for DatabaseError {
+    fn from(error: std::io::Error) -> Self {
+        DatabaseError::Io(error)
+    }
+}
+
+/// The examples read from a database, and the lines skipped in lenient mode.
+struct Database {
+    examples: Vec<Example>,
+    warnings: Vec<ParseIssue>,
+}
+
 struct Example {
+    /// The 1-based line the example was read from, 0 if it was built in code.
+    line: usize,
     password: String,
-    policies: Vec<Policy>,
+    policies: Vec<Rule>,
 }
 
 impl Example {
     fn new(password: &str) -> Self {
         Example {
+            line: 0,
             password: password.to_string(),
             policies: vec![],
         }
     }
 
-    fn read_all(path: &str) -> std::io::Result<Vec<Example>> {
+    /// Reads the password database.  A line may start with a policy kind
+    /// (`sled 1-3 a: abcde`); lines without one use `default_kind`.
+    fn read_all(path: &str, parser: &LineParser, mode: ParseMode) -> Result<Database, DatabaseError> {
         let data_file = std::fs::File::open(path)?;
-        let reader = BufReader::new(data_file);
-
-        let mut result: Vec<Example> = vec![];
-        let re = regex::Regex::new("(\\d+)-(\\d+)\\s+([a-z])\\s*:\\s*(\\w+)").expect("Should have a legit RE");
-        for line in reader.lines() {
-            match line {
-                Err(error) => return Err(std::io::Error::from(error)),
-                Ok(raw_line) => {
-                    if raw_line.trim().len() == 0 {
-                        return Ok(result);
-                    } else {
-                        for capture in re.captures_iter(raw_line.trim()) {
-                            let min = &capture[1].parse().unwrap_or_default();
-                            let max = &capture[2].parse().unwrap_or_default();
-                            let character: char = capture[3].chars().next().unwrap();
-                            let password = &capture[4];
-
-                            let policy = Policy::new(*min, *max, character);
-                            let mut example = Example::new(password);
-                            example.add_policy(policy);
-                            result.push(example);
-                        }
-                    }
-                }
+        Example::read_from(BufReader::new(data_file), parser, mode)
+    }
+
+    /// Parses every line of the database.  Blank lines are skipped.  In
+    /// strict mode any malformed line fails the whole read, with every
+    /// problem listed; in lenient mode malformed lines are dropped and kept
+    /// as warnings.
+    fn read_from<R: BufRead>(reader: R, parser: &LineParser, mode: ParseMode) -> Result<Database, DatabaseError> {
+        let mut result = Database { examples: vec![], warnings: vec![] };
+
+        for (line_number, line) in reader.lines().enumerate() {
+            let raw_line = line?;
+            if raw_line.trim().is_empty() {
+                continue;
+            }
+
+            match parser.parse(&raw_line) {
+                Ok(mut example) => {
+                    example.line = line_number + 1;
+                    result.examples.push(example);
+                },
+                Err(reason) => result.warnings.push(ParseIssue {
+                    line: line_number + 1,
+                    text: raw_line,
+                    reason,
+                }),
             }
         }
 
+        if mode == ParseMode::Strict && !result.warnings.is_empty() {
+            return Err(DatabaseError::Malformed(result.warnings));
+        }
+
         Ok(result)
     }
 
-    fn add_policy(&mut self, policy: Policy) {
-        self.policies.push(policy)
+    fn add_policy<P: Into<Rule>>(&mut self, policy: P) {
+        self.policies.push(policy.into())
     }
 
-    fn evaluate(&self) -> bool {
+    /// Checks every policy and explains each outcome.  Use `passes` when
+    /// only the answer is needed.
+    fn evaluate(&self) -> Evaluation {
+        Evaluation {
+            verdicts: self.policies.iter().map(|policy| policy.explain(&self.password)).collect(),
+        }
+    }
+
+    fn passes(&self) -> bool {
         for policy in self.policies.as_slice() {
             if !policy.evalute(&self.password) {
                 return false;
@@ -91,12 +346,218 @@ impl Example {
     }
 }
 
+/// Quotes a CSV field when it holds a separator, quote or line break.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Writes one row per failed policy: line, password, policy and reason.
+fn write_csv_report<W: Write>(out: &mut W, examples: &[Example]) -> std::io::Result<()> {
+    writeln!(out, "line,password,policy,reason")?;
+    for example in examples {
+        for failure in example.evaluate().failures() {
+            writeln!(out, "{},{},{},{}", example.line, csv_field(&example.password),
+                csv_field(&failure.policy), csv_field(&failure.reason))?;
+        }
+    }
+    Ok(())
+}
+
+fn print_report(examples: &[Example]) {
+    for example in examples {
+        let evaluation = example.evaluate();
+        if evaluation.passed() {
+            continue;
+        }
+
+        println!("line {}: {}", example.line, example.password);
+        for failure in evaluation.failures() {
+            println!("    {}: {}", failure.policy, failure.reason);
+        }
+    }
+}
+
+/// Audits the database without loading it, then prints the counts.  With
+/// `report` the first few failures are printed too.
+fn print_audit(path: &str, parser: &LineParser, rules: Option<&[Rule]>, options: &AuditOptions, report: bool) {
+    let summary = std::fs::File::open(path)
+        .map_err(DatabaseError::from)
+        .and_then(|file| stream::audit(BufReader::with_capacity(1 << 20, file), parser, rules, options));
+
+    match summary {
+        Ok(summary) => {
+            for issue in &summary.issues {
+                println!("Skipped {}", issue);
+            }
+            if report {
+                print_report(&summary.failures);
+                if summary.failed > summary.failures.len() {
+                    println!("... and {} more failures", summary.failed - summary.failures.len());
+                }
+            }
+            println!("There are {} good and {} failed ({} malformed lines)", summary.good, summary.failed, summary.malformed);
+        },
+        Err(error) => println!("{}", error),
+    }
+}
+
+/// Prints passwords that pass the rule, then the smallest ones that fail it.
+fn print_samples(rule: Rule, count: usize, seed: u64) {
+    let mut example = Example::new("");
+    example.add_policy(rule);
+
+    let mut generator = Generator::new(seed);
+    for _ in 0..count {
+        match generator.satisfying_example(&example, 1000) {
+            Some(password) => println!("pass  {}", password),
+            None => {
+                println!("No password found that satisfies {}", example.policies[0]);
+                break;
+            },
+        }
+    }
+
+    for violation in Generator::example_violations(&example) {
+        println!("fail  {}  ({})", violation.password, violation.description);
+    }
+}
+
 fn main() {
-    let all_examples = Example::read_all("./input.txt").expect("Should load sample database");
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let mut default_kind = PolicyKind::Toboggan;
+    let mut path = String::from("./input.txt");
+    let mut rules: Option<Vec<Rule>> = None;
+    let mut report = false;
+    let mut csv_path: Option<String> = None;
+    let mut mode = ParseMode::Lenient;
+    let mut ignore_case = false;
+    let mut generate: Option<String> = None;
+    let mut count = 5;
+    let mut seed = 2020;
+    let mut streaming = false;
+    let mut workers = std::thread::available_parallelism().map(|count| count.get()).unwrap_or(4);
+    let mut idx = 0;
+    while idx < args.len() {
+        match args[idx].as_str() {
+            "--policy" => {
+                idx += 1;
+                let name = args.get(idx).expect("--policy needs a kind (sled or toboggan)");
+                default_kind = name.parse().expect("Should be a known policy kind");
+            },
+            "--rules" => {
+                idx += 1;
+                let rule_path = args.get(idx).expect("--rules needs a rule file");
+                match Rule::read_all(rule_path) {
+                    Ok(loaded) => rules = Some(loaded),
+                    Err(error) => {
+                        println!("{}", error);
+                        return;
+                    },
+                }
+            },
+            "--report" => report = true,
+            "--strict" => mode = ParseMode::Strict,
+            "--ignore-case" => ignore_case = true,
+            "--generate" => {
+                idx += 1;
+                generate = Some(args.get(idx).expect("--generate needs a rule such as 'sled 1-3 a'").to_string());
+            },
+            "--count" => {
+                idx += 1;
+                count = args.get(idx).and_then(|text| text.parse().ok()).expect("--count needs a number");
+            },
+            "--stream" => streaming = true,
+            "--workers" => {
+                idx += 1;
+                workers = args.get(idx).and_then(|text| text.parse().ok()).expect("--workers needs a number");
+            },
+            "--seed" => {
+                idx += 1;
+                seed = args.get(idx).and_then(|text| text.parse().ok()).expect("--seed needs a number");
+            },
+            "--csv" => {
+                idx += 1;
+                csv_path = Some(args.get(idx).expect("--csv needs an output file").to_string());
+            },
+            other if other.starts_with("--") => {
+                println!("Unknown option '{}'", other);
+                return;
+            },
+            other => path = other.to_string(),
+        }
+        idx += 1;
+    }
+
+    if let Some(rule_text) = generate {
+        let mut rule = match Rule::parse(&rule_text) {
+            Ok(rule) => rule,
+            Err(error) => {
+                println!("Bad rule '{}': {}", rule_text, error);
+                return;
+            },
+        };
+        if ignore_case {
+            rule.ignore_case();
+        }
+        print_samples(rule, count, seed);
+        return;
+    }
+
+    if ignore_case {
+        rules.iter_mut().flatten().for_each(|rule| rule.ignore_case());
+    }
+
+    let parser = LineParser::new(default_kind, ignore_case);
+    if streaming {
+        // Only a sample of the failures is kept, so there is no full list to write.
+        if csv_path.is_some() {
+            println!("--csv needs every failure and can't be combined with --stream");
+            return;
+        }
+        let options = AuditOptions { workers, chunk_size: 4096, sample_size: 20, mode };
+        print_audit(&path, &parser, rules.as_deref(), &options, report);
+        return;
+    }
+
+    let database = match Example::read_all(&path, &parser, mode) {
+        Ok(database) => database,
+        Err(error) => {
+            println!("{}", error);
+            return;
+        },
+    };
+    for warning in &database.warnings {
+        println!("Skipped {}", warning);
+    }
+
+    let mut all_examples = database.examples;
+    // A rule file stands in for the policy written on each line.
+    if let Some(rules) = rules {
+        for example in all_examples.iter_mut() {
+            example.policies = rules.clone();
+        }
+    }
+
+    if report {
+        print_report(&all_examples);
+    }
+
+    if let Some(csv_path) = csv_path {
+        let written = std::fs::File::create(&csv_path)
+            .and_then(|file| write_csv_report(&mut std::io::BufWriter::new(file), &all_examples));
+        if let Err(error) = written {
+            println!("Failed to write {}: {}", csv_path, error);
+        }
+    }
+
     let mut counter = 0;
     let mut failed = 0;
     for example in all_examples {
-        if example.evaluate() {
+        if example.passes() {
             counter += 1;
         } else {
             failed += 1;
@@ -119,11 +580,79 @@ mod tests {
         ];
 
         for example in examples {
-            let policy = Policy::new(example.0, example.1, example.2);
+            let policy = Policy::new(PolicyKind::SledRental, example.0, example.1, example.2);
             let mut test_example = Example::new(example.3);
             test_example.add_policy(policy);
 
-            assert_eq!(test_example.evaluate(), example.4);
+            assert_eq!(test_example.evaluate().passed(), example.4);
         }
     }
+
+    #[test]
+    fn check_toboggan_evaluation() {
+        let examples = &[
+            (1, 3, 'a', "abcde", true),
+            (1, 3, 'b', "cdefg", false),
+            (2, 9, 'c', "ccccccccc", false),
+        ];
+
+        for example in examples {
+            let policy = Policy::new(PolicyKind::Toboggan, example.0, example.1, example.2);
+            assert_eq!(policy.evalute(example.3), example.4);
+        }
+    }
+
+    #[test]
+    fn check_failure_reasons() {
+        let mut example = Example::new("ccccccccc");
+        example.add_policy(Policy::new(PolicyKind::Toboggan, 1, 3, 'c'));
+        example.add_policy(Policy::new(PolicyKind::SledRental, 1, 3, 'c'));
+        example.add_policy(Policy::new(PolicyKind::Toboggan, 2, 12, 'c'));
+
+        let evaluation = example.evaluate();
+        assert!(!evaluation.passed());
+
+        let reasons: Vec<&str> = evaluation.failures().map(|verdict| verdict.reason.as_str()).collect();
+        assert_eq!(reasons, vec![
+            "position 1 is 'c', position 3 is 'c': both match",
+            "'c' appears 9 times, expected 1-3",
+        ]);
+        assert_eq!(evaluation.verdicts[2].reason, "position 2 is 'c', position 12 is past the end: only position 2 matches");
+    }
+
+    #[test]
+    fn check_strict_and_lenient_parsing() {
+        let database = "1-3 a: abcde\n\n3-1 b: cdefg\nsled 0-2 c: ccc\ntoboggan 0-2 c: ccc\nnonsense\n99999999999-1 d: d\n2-9 c: ccccccccc\n";
+
+        match Example::read_from(database.as_bytes(), &LineParser::new(PolicyKind::Toboggan, false), ParseMode::Strict) {
+            Err(DatabaseError::Malformed(issues)) => {
+                let lines: Vec<usize> = issues.iter().map(|issue| issue.line).collect();
+                assert_eq!(lines, vec![3, 5, 6, 7]);
+                assert_eq!(issues[0].reason, "min 3 is above max 1");
+                assert_eq!(issues[1].reason, "positions start at 1, found 0");
+            },
+            _ => panic!("Strict parsing should reject the malformed lines"),
+        }
+
+        let lenient = Example::read_from(database.as_bytes(), &LineParser::new(PolicyKind::Toboggan, false), ParseMode::Lenient)
+            .expect("Lenient parsing should succeed");
+        let lines: Vec<usize> = lenient.examples.iter().map(|example| example.line).collect();
+        assert_eq!(lines, vec![1, 4, 8]);
+        assert_eq!(lenient.warnings.len(), 4);
+    }
+
+    #[test]
+    fn check_unicode_passwords() {
+        let parser = LineParser::new(PolicyKind::Toboggan, true);
+        let database = "1-3 É: étÉ\nsled 2-2 ß: straße Spaß\n1-2 e: e\u{301}e\n";
+        let examples = Example::read_from(database.as_bytes(), &parser, ParseMode::Strict)
+            .expect("Should accept unicode policies").examples;
+
+        // Both positions hold an É once case is ignored.
+        assert!(!examples[0].passes());
+        assert_eq!(examples[0].password, "étÉ");
+        assert!(examples[1].passes());
+        // An e with a combining accent is one character, and not an 'e'.
+        assert!(examples[2].passes());
+    }
 }
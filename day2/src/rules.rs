@@ -0,0 +1,614 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{prelude::*, BufReader};
+
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::{Policy, PolicyKind, Verdict};
+
+/// A set of characters a rule can count or look for.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CharClass {
+    Any,
+    Digit,
+    Upper,
+    Lower,
+    Alpha,
+    Alnum,
+    Space,
+    /// Anything that is not alphanumeric or whitespace.
+    Symbol,
+    /// Inclusive ranges from a bracket set such as `[a-f0-9_]`.
+    Set(Vec<(char, char)>),
+}
+
+impl CharClass {
+    pub fn contains(&self, c: char) -> bool {
+        match self {
+            CharClass::Any => true,
+            CharClass::Digit => c.is_numeric(),
+            CharClass::Upper => c.is_uppercase(),
+            CharClass::Lower => c.is_lowercase(),
+            CharClass::Alpha => c.is_alphabetic(),
+            CharClass::Alnum => c.is_alphanumeric(),
+            CharClass::Space => c.is_whitespace(),
+            CharClass::Symbol => !c.is_alphanumeric() && !c.is_whitespace(),
+            CharClass::Set(ranges) => ranges.iter().any(|(low, high)| *low <= c && c <= *high),
+        }
+    }
+
+    /// Classifies a grapheme cluster by its base character, so an accented
+    /// letter built from combining marks still counts as a letter.
+    pub fn contains_grapheme(&self, grapheme: &str) -> bool {
+        grapheme.chars().next().is_some_and(|c| self.contains(c))
+    }
+}
+
+impl fmt::Display for CharClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CharClass::Any => f.write_str("any"),
+            CharClass::Digit => f.write_str("digit"),
+            CharClass::Upper => f.write_str("upper"),
+            CharClass::Lower => f.write_str("lower"),
+            CharClass::Alpha => f.write_str("alpha"),
+            CharClass::Alnum => f.write_str("alnum"),
+            CharClass::Space => f.write_str("space"),
+            CharClass::Symbol => f.write_str("symbol"),
+            CharClass::Set(ranges) => {
+                f.write_str("[")?;
+                for (low, high) in ranges {
+                    let members = if low == high { vec![(*low, true)] } else { vec![(*low, true), ('-', false), (*high, true)] };
+                    for (c, member) in members {
+                        // A `-` that is a member rather than a range is escaped too.
+                        if c == ']' || c == '\\' || (member && c == '-') {
+                            f.write_str("\\")?;
+                        }
+                        write!(f, "{}", c)?;
+                    }
+                }
+                f.write_str("]")
+            },
+        }
+    }
+}
+
+/// Writes a range the way the rule file spells it.
+fn fmt_range(f: &mut fmt::Formatter<'_>, min: usize, max: usize) -> fmt::Result {
+    if max == usize::MAX {
+        write!(f, "{}+", min)
+    } else if min == max {
+        write!(f, "{}", min)
+    } else {
+        write!(f, "{}-{}", min, max)
+    }
+}
+
+/// A password rule.  The classic puzzle policies are leaves alongside
+/// length, count and position checks, combined with `and`, `or` and `not`.
+#[derive(Clone)]
+pub enum Rule {
+    Policy(Policy),
+    Length { min: usize, max: usize },
+    Count { class: CharClass, min: usize, max: usize },
+    /// The 1-based position holds a character from the class.
+    At { position: usize, class: CharClass },
+    Not(Box<Rule>),
+    And(Vec<Rule>),
+    Or(Vec<Rule>),
+}
+
+impl From<Policy> for Rule {
+    fn from(policy: Policy) -> Self {
+        Rule::Policy(policy)
+    }
+}
+
+impl Rule {
+    /// Lengths, counts and positions are all in grapheme clusters.
+    pub fn evalute(&self, test_string: &str) -> bool {
+        match self {
+            Rule::Policy(policy) => policy.evalute(test_string),
+            Rule::Length { min, max } => {
+                let length = test_string.graphemes(true).count();
+                length >= *min && length <= *max
+            },
+            Rule::Count { class, min, max } => {
+                let count = test_string.graphemes(true).filter(|g| class.contains_grapheme(g)).count();
+                count >= *min && count <= *max
+            },
+            Rule::At { position, class } => {
+                match position.checked_sub(1).and_then(|idx| test_string.graphemes(true).nth(idx)) {
+                    Some(g) => class.contains_grapheme(g),
+                    None => false,
+                }
+            },
+            Rule::Not(rule) => !rule.evalute(test_string),
+            Rule::And(rules) => rules.iter().all(|rule| rule.evalute(test_string)),
+            Rule::Or(rules) => rules.iter().any(|rule| rule.evalute(test_string)),
+        }
+    }
+
+    pub fn explain(&self, test_string: &str) -> Verdict {
+        let (passed, reason) = match self {
+            Rule::Policy(policy) => return policy.explain(test_string),
+            Rule::Length { .. } => {
+                let length = test_string.graphemes(true).count();
+                (self.evalute(test_string), format!("length is {}", length))
+            },
+            Rule::Count { class, .. } => {
+                let count = test_string.graphemes(true).filter(|g| class.contains_grapheme(g)).count();
+                (self.evalute(test_string), format!("{} characters are {}", count, class))
+            },
+            Rule::At { position, class } => {
+                match position.checked_sub(1).and_then(|idx| test_string.graphemes(true).nth(idx)) {
+                    Some(g) if class.contains_grapheme(g) => (true, format!("position {} is '{}'", position, g)),
+                    Some(g) => (false, format!("position {} is '{}', not {}", position, g, class)),
+                    None => (false, format!("position {} is past the end", position)),
+                }
+            },
+            Rule::Not(rule) => {
+                let inner = rule.explain(test_string);
+                let holds = if inner.passed { "holds" } else { "does not hold" };
+                (!inner.passed, format!("{} {}: {}", inner.policy, holds, inner.reason))
+            },
+            Rule::And(rules) => {
+                let verdicts: Vec<Verdict> = rules.iter().map(|rule| rule.explain(test_string)).collect();
+                let failed: Vec<String> = verdicts.iter()
+                    .filter(|verdict| !verdict.passed)
+                    .map(|verdict| format!("{}: {}", verdict.policy, verdict.reason))
+                    .collect();
+                if failed.is_empty() {
+                    (true, String::from("every part holds"))
+                } else {
+                    (false, failed.join("; "))
+                }
+            },
+            Rule::Or(rules) => {
+                let verdicts: Vec<Verdict> = rules.iter().map(|rule| rule.explain(test_string)).collect();
+                match verdicts.iter().find(|verdict| verdict.passed) {
+                    Some(verdict) => (true, format!("{}: {}", verdict.policy, verdict.reason)),
+                    None => {
+                        let reasons: Vec<String> = verdicts.iter()
+                            .map(|verdict| format!("{}: {}", verdict.policy, verdict.reason))
+                            .collect();
+                        (false, format!("no alternative holds ({})", reasons.join("; ")))
+                    },
+                }
+            },
+        };
+
+        Verdict {
+            policy: self.to_string(),
+            passed,
+            reason,
+        }
+    }
+
+    /// Makes every puzzle policy in the rule compare characters without
+    /// regard to case.
+    pub fn ignore_case(&mut self) {
+        match self {
+            Rule::Policy(policy) => policy.ignore_case = true,
+            Rule::Not(rule) => rule.ignore_case(),
+            Rule::And(rules) | Rule::Or(rules) => rules.iter_mut().for_each(|rule| rule.ignore_case()),
+            _ => {},
+        }
+    }
+
+    /// Reads a rule file: one expression per line, blank lines and lines
+    /// starting with `#` ignored.
+    ///
+    /// ```text
+    /// length 12+
+    /// count digit 1+ and count upper 1+
+    /// not (at 1 symbol or count [0-9] 6+)
+    /// toboggan 1-3 a
+    /// sled 1-2 'É' nocase
+    /// ```
+    pub fn read_all(path: &str) -> Result<Vec<Rule>, RuleError> {
+        let rule_file = std::fs::File::open(path)
+            .map_err(|error| RuleError::new(0, &error.to_string()))?;
+        let reader = BufReader::new(rule_file);
+
+        let mut result: Vec<Rule> = vec![];
+        for (idx, line) in reader.lines().enumerate() {
+            let raw_line = line.map_err(|error| RuleError::new(idx + 1, &error.to_string()))?;
+            let trimmed = raw_line.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+
+            let rule = Rule::parse(trimmed).map_err(|message| RuleError::new(idx + 1, &message))?;
+            result.push(rule);
+        }
+
+        Ok(result)
+    }
+
+    pub fn parse(text: &str) -> Result<Rule, String> {
+        let mut parser = Parser { tokens: tokenize(text)?, position: 0 };
+        let rule = parser.parse_or()?;
+        match parser.peek() {
+            None => Ok(rule),
+            Some(token) => Err(format!("Unexpected '{}'", token)),
+        }
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Nested `and`/`or` groups are parenthesised so the text parses back
+        // to the same rule.
+        let grouped = |f: &mut fmt::Formatter<'_>, rule: &Rule| match rule {
+            Rule::And(_) | Rule::Or(_) => write!(f, "({})", rule),
+            _ => write!(f, "{}", rule),
+        };
+
+        match self {
+            Rule::Policy(policy) => write!(f, "{}", policy),
+            Rule::Length { min, max } => {
+                f.write_str("length ")?;
+                fmt_range(f, *min, *max)
+            },
+            Rule::Count { class, min, max } => {
+                write!(f, "count {} ", class)?;
+                fmt_range(f, *min, *max)
+            },
+            Rule::At { position, class } => write!(f, "at {} {}", position, class),
+            Rule::Not(rule) => {
+                f.write_str("not ")?;
+                grouped(f, rule)
+            },
+            Rule::And(rules) | Rule::Or(rules) => {
+                let joiner = if let Rule::And(_) = self { " and " } else { " or " };
+                for (idx, rule) in rules.iter().enumerate() {
+                    if idx > 0 {
+                        f.write_str(joiner)?;
+                    }
+                    grouped(f, rule)?;
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct RuleError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl RuleError {
+    fn new(line: usize, message: &str) -> Self {
+        RuleError { line, message: message.to_string() }
+    }
+}
+
+impl fmt::Display for RuleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Rule line {}: {}", self.line, self.message)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    Word(String),
+    Class(CharClass),
+    Literal(String),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Open => f.write_str("("),
+            Token::Close => f.write_str(")"),
+            Token::Word(word) => f.write_str(word),
+            Token::Class(class) => write!(f, "{}", class),
+            Token::Literal(c) => write!(f, "'{}'", c),
+        }
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens: Vec<Token> = vec![];
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            _ if c.is_whitespace() => {},
+            '(' => tokens.push(Token::Open),
+            ')' => tokens.push(Token::Close),
+            '\'' => {
+                let mut literal = String::new();
+                loop {
+                    match chars.next() {
+                        Some('\'') if !literal.is_empty() => break,
+                        Some(c) => literal.push(c),
+                        None => return Err(String::from("Unterminated character literal")),
+                    }
+                }
+                if literal.graphemes(true).count() != 1 {
+                    return Err(format!("'{}' is not a single character", literal));
+                }
+                tokens.push(Token::Literal(literal));
+            },
+            '[' => {
+                // Each member is paired with whether it was escaped.
+                let mut members: Vec<(char, bool)> = vec![];
+                loop {
+                    match chars.next() {
+                        Some(']') => break,
+                        Some('\\') => members.push((chars.next().ok_or("Unterminated character set")?, true)),
+                        Some(member) => members.push((member, false)),
+                        None => return Err(String::from("Unterminated character set")),
+                    }
+                }
+                tokens.push(Token::Class(CharClass::Set(set_ranges(&members)?)));
+            },
+            _ => {
+                let mut word = String::from(c);
+                while let Some(&next) = chars.peek() {
+                    if next.is_whitespace() || next == '(' || next == ')' {
+                        break;
+                    }
+                    word.push(next);
+                    chars.next();
+                }
+                tokens.push(Token::Word(word));
+            },
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Turns the members of a bracket set into ranges, so `a-f_` becomes
+/// `a..=f` and `_..=_`.  A `-` at either end or escaped as `\-` is taken
+/// literally.
+fn set_ranges(members: &[(char, bool)]) -> Result<Vec<(char, char)>, String> {
+    if members.is_empty() {
+        return Err(String::from("Empty character set"));
+    }
+
+    let mut ranges: Vec<(char, char)> = vec![];
+    let mut idx = 0;
+    while idx < members.len() {
+        if idx + 2 < members.len() && members[idx + 1] == ('-', false) {
+            let (low, high) = (members[idx].0, members[idx + 2].0);
+            if low > high {
+                return Err(format!("Backwards range {}-{}", low, high));
+            }
+            ranges.push((low, high));
+            idx += 3;
+        } else {
+            ranges.push((members[idx].0, members[idx].0));
+            idx += 1;
+        }
+    }
+
+    Ok(ranges)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Result<Token, String> {
+        let token = self.tokens.get(self.position).cloned().ok_or("Unexpected end of rule")?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn at_word(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Word(word)) if word == keyword)
+    }
+
+    fn parse_or(&mut self) -> Result<Rule, String> {
+        let mut rules = vec![self.parse_and()?];
+        while self.at_word("or") {
+            self.position += 1;
+            rules.push(self.parse_and()?);
+        }
+        Ok(if rules.len() == 1 { rules.remove(0) } else { Rule::Or(rules) })
+    }
+
+    fn parse_and(&mut self) -> Result<Rule, String> {
+        let mut rules = vec![self.parse_unary()?];
+        while self.at_word("and") {
+            self.position += 1;
+            rules.push(self.parse_unary()?);
+        }
+        Ok(if rules.len() == 1 { rules.remove(0) } else { Rule::And(rules) })
+    }
+
+    fn parse_unary(&mut self) -> Result<Rule, String> {
+        match self.next()? {
+            Token::Open => {
+                let rule = self.parse_or()?;
+                match self.next()? {
+                    Token::Close => Ok(rule),
+                    token => Err(format!("Expected ')' but found '{}'", token)),
+                }
+            },
+            Token::Word(word) => match word.as_str() {
+                "not" => Ok(Rule::Not(Box::new(self.parse_unary()?))),
+                "length" => {
+                    let (min, max) = parse_range(&self.word()?)?;
+                    Ok(Rule::Length { min, max })
+                },
+                "count" => {
+                    let class = self.class()?;
+                    let (min, max) = parse_range(&self.word()?)?;
+                    Ok(Rule::Count { class, min, max })
+                },
+                "at" => {
+                    let position = parse_number(&self.word()?)?;
+                    if position == 0 {
+                        return Err(String::from("Positions start at 1"));
+                    }
+                    Ok(Rule::At { position, class: self.class()? })
+                },
+                _ => {
+                    let kind: PolicyKind = word.parse()?;
+                    let (min, max) = parse_range(&self.word()?)?;
+                    let character = match self.next()? {
+                        Token::Literal(literal) => literal,
+                        Token::Word(word) if word.graphemes(true).count() == 1 => word,
+                        token => return Err(format!("Expected a character but found '{}'", token)),
+                    };
+                    let bound = |value: usize| i32::try_from(value)
+                        .map_err(|_| format!("{} policies need an upper bound", kind));
+                    let mut policy = Policy::new(kind, bound(min)?, bound(max)?, character);
+                    if self.at_word("nocase") {
+                        self.position += 1;
+                        policy.ignore_case = true;
+                    }
+                    policy.validate()?;
+                    Ok(Rule::Policy(policy))
+                },
+            },
+            token => Err(format!("Unexpected '{}'", token)),
+        }
+    }
+
+    fn word(&mut self) -> Result<String, String> {
+        match self.next()? {
+            Token::Word(word) => Ok(word),
+            token => Err(format!("Expected a number or range but found '{}'", token)),
+        }
+    }
+
+    fn class(&mut self) -> Result<CharClass, String> {
+        match self.next()? {
+            Token::Class(class) => Ok(class),
+            Token::Literal(literal) => {
+                let mut chars = literal.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => Ok(CharClass::Set(vec![(c, c)])),
+                    _ => Err(format!("'{}' is more than one code point and cannot be in a class", literal)),
+                }
+            },
+            Token::Word(word) => match word.as_str() {
+                "any" => Ok(CharClass::Any),
+                "digit" => Ok(CharClass::Digit),
+                "upper" => Ok(CharClass::Upper),
+                "lower" => Ok(CharClass::Lower),
+                "alpha" => Ok(CharClass::Alpha),
+                "alnum" => Ok(CharClass::Alnum),
+                "space" => Ok(CharClass::Space),
+                "symbol" => Ok(CharClass::Symbol),
+                _ => Err(format!("Unknown character class '{}'", word)),
+            },
+            token => Err(format!("Expected a character class but found '{}'", token)),
+        }
+    }
+}
+
+fn parse_number(text: &str) -> Result<usize, String> {
+    text.parse().map_err(|_| format!("'{}' is not a number", text))
+}
+
+/// Ranges are written `3-8`, `12+` (at least 12) or `4` (exactly 4).
+fn parse_range(text: &str) -> Result<(usize, usize), String> {
+    if let Some(min) = text.strip_suffix('+') {
+        return Ok((parse_number(min)?, usize::MAX));
+    }
+
+    match text.split_once('-') {
+        Some((min, max)) => {
+            let (min, max) = (parse_number(min)?, parse_number(max)?);
+            if min > max {
+                return Err(format!("Range {} has min above max", text));
+            }
+            Ok((min, max))
+        },
+        None => {
+            let exact = parse_number(text)?;
+            Ok((exact, exact))
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_rule_evaluation() {
+        let examples = &[
+            ("length 8+", "abcdefgh", true),
+            ("length 8+", "abcdefg", false),
+            ("count digit 2-3 and count upper 1+", "Abc12", true),
+            ("count digit 2-3 and count upper 1+", "abc12", false),
+            ("at 1 symbol or at 1 [A-Z]", "Pass", true),
+            ("at 1 symbol or at 1 [A-Z]", "pass", false),
+            ("not (count 'a' 2+)", "banana", false),
+            ("count [a\\-z] 3", "a-z", true),
+            ("count [a\\-z] 1+", "m", false),
+            ("count [-a-c] 2", "-b", true),
+            ("sled 1-3 a and not toboggan 1-3 'a'", "abcde", false),
+            ("sled 1-3 a and not toboggan 1-3 'a'", "bacad", true),
+        ];
+
+        for example in examples {
+            let rule = Rule::parse(example.0).expect("Should parse rule");
+            assert_eq!(rule.evalute(example.1), example.2, "{} on {}", example.0, example.1);
+        }
+    }
+
+    #[test]
+    fn check_rule_errors() {
+        assert!(Rule::parse("length").is_err());
+        assert!(Rule::parse("count digit 5-2").is_err());
+        assert!(Rule::parse("at 0 digit").is_err());
+        assert!(Rule::parse("(length 3").is_err());
+        assert!(Rule::parse("length 3 length 4").is_err());
+        assert!(Rule::parse("count [z-a] 1").is_err());
+        assert!(Rule::parse("toboggan 0-3 a").is_err());
+        assert!(Rule::parse("sled 2+ a").is_err());
+        assert!(Rule::parse("sled 1-2 'ab'").is_err());
+        assert_eq!(Rule::parse("length 3 [a-z]").err().as_deref(), Some("Unexpected '[a-z]'"));
+
+        let built = Rule::At { position: 0, class: CharClass::Any };
+        assert!(!built.evalute("abc"));
+        assert!(!built.explain("abc").passed);
+    }
+
+    #[test]
+    fn check_unicode_rules() {
+        // "e\u{301}" is e with a combining acute accent: two code points, one character.
+        let password = "Cafe\u{301}👍🏽";
+        assert!(Rule::parse("length 5").unwrap().evalute(password));
+        assert!(Rule::parse("at 4 lower and at 5 symbol").unwrap().evalute(password));
+
+        let rule = Rule::parse("toboggan 4-5 'e\u{301}' and sled 1-1 'c' nocase").unwrap();
+        assert!(rule.evalute(password));
+        assert_eq!(rule.to_string(), "toboggan 4-5 'e\u{301}' and sled 1-1 'c' nocase");
+    }
+
+    #[test]
+    fn check_rule_round_trip() {
+        let text = "not (length 12+ or count [a-f\\]] 2-4) and at 3 digit";
+        let rule = Rule::parse(text).expect("Should parse rule");
+        assert_eq!(rule.to_string(), text);
+        assert_eq!(Rule::parse("count [a\\-z-] 1").unwrap().to_string(), "count [a\\-z\\-] 1");
+
+        let verdict = rule.explain("xy1");
+        assert!(verdict.passed);
+        assert_eq!(verdict.reason, "every part holds");
+
+        let verdict = rule.explain("xyz");
+        assert!(!verdict.passed);
+        assert_eq!(verdict.reason, "at 3 digit: position 3 is 'z', not digit");
+    }
+}
@@ -0,0 +1,217 @@
+use std::io::BufRead;
+use std::sync::mpsc::{sync_channel, Receiver};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::rules::Rule;
+use crate::{DatabaseError, Example, LineParser, ParseIssue, ParseMode};
+
+/// Counts from auditing a database, plus the first failures and malformed
+/// lines by line number.  Only the samples are kept, so memory does not
+/// grow with the size of the dump.
+pub struct AuditSummary {
+    pub good: usize,
+    pub failed: usize,
+    pub malformed: usize,
+    pub failures: Vec<Example>,
+    pub issues: Vec<ParseIssue>,
+}
+
+impl AuditSummary {
+    fn new() -> Self {
+        AuditSummary { good: 0, failed: 0, malformed: 0, failures: vec![], issues: vec![] }
+    }
+
+    /// Folds another worker's results in, keeping the earliest samples.
+    fn merge(&mut self, other: AuditSummary, sample_size: usize) {
+        self.good += other.good;
+        self.failed += other.failed;
+        self.malformed += other.malformed;
+        self.failures.extend(other.failures);
+        self.issues.extend(other.issues);
+
+        self.failures.sort_by_key(|example| example.line);
+        self.failures.truncate(sample_size);
+        self.issues.sort_by_key(|issue| issue.line);
+        self.issues.truncate(sample_size);
+    }
+}
+
+/// How a streaming audit splits up the work.
+pub struct AuditOptions {
+    pub workers: usize,
+    /// Lines handed to a worker at a time.
+    pub chunk_size: usize,
+    /// Failures and malformed lines to keep for reporting.
+    pub sample_size: usize,
+    /// In strict mode the audit stops at the first malformed line.
+    pub mode: ParseMode,
+}
+
+/// Parses and checks a database of any size.  The calling thread reads
+/// chunks of lines and hands them to `workers` threads over a bounded
+/// channel, so at most a few chunks are in memory at once.  Bytes that are
+/// not UTF-8 are replaced rather than failing the read.  When `rules` is
+/// given it stands in for the policy written on each line.  In strict mode
+/// a malformed line fails the audit with the malformed lines seen so far.
+pub fn audit<R: BufRead>(mut reader: R, parser: &LineParser, rules: Option<&[Rule]>, options: &AuditOptions) -> Result<AuditSummary, DatabaseError> {
+    let workers = options.workers.max(1);
+    let (sender, receiver) = sync_channel::<Vec<(usize, String)>>(workers * 2);
+    let receiver = Mutex::new(receiver);
+    let stop = AtomicBool::new(false);
+
+    let result = thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers)
+            .map(|_| scope.spawn(|| audit_chunks(&receiver, parser, rules, options, &stop)))
+            .collect();
+
+        let mut line_number = 0;
+        let mut buffer: Vec<u8> = vec![];
+        let mut chunk: Vec<(usize, String)> = Vec::with_capacity(options.chunk_size);
+        let read_result = loop {
+            buffer.clear();
+            if stop.load(Ordering::Relaxed) {
+                break Ok(());
+            }
+            match reader.read_until(b'\n', &mut buffer) {
+                Ok(0) => break Ok(()),
+                Ok(_) => {
+                    line_number += 1;
+                    chunk.push((line_number, String::from_utf8_lossy(&buffer).into_owned()));
+                    if chunk.len() >= options.chunk_size {
+                        let full = std::mem::replace(&mut chunk, Vec::with_capacity(options.chunk_size));
+                        if sender.send(full).is_err() {
+                            break Ok(());
+                        }
+                    }
+                },
+                Err(error) => break Err(error),
+            }
+        };
+        if read_result.is_ok() && !chunk.is_empty() {
+            let _ = sender.send(chunk);
+        }
+        // Closing the channel lets the workers finish.
+        drop(sender);
+
+        let mut summary = AuditSummary::new();
+        for handle in handles {
+            summary.merge(handle.join().expect("Audit worker panicked"), options.sample_size);
+        }
+        read_result.map(|_| summary)
+    })?;
+
+    if options.mode == ParseMode::Strict && result.malformed > 0 {
+        return Err(DatabaseError::Malformed(result.issues));
+    }
+    Ok(result)
+}
+
+/// Chunks go out in line order and each worker takes them in turn, so a
+/// worker sees its lines in increasing order and its first failures are the
+/// ones worth keeping.
+fn audit_chunks(receiver: &Mutex<Receiver<Vec<(usize, String)>>>, parser: &LineParser, rules: Option<&[Rule]>,
+    options: &AuditOptions, stop: &AtomicBool) -> AuditSummary {
+    let sample_size = options.sample_size;
+    let mut summary = AuditSummary::new();
+    loop {
+        // Hold the lock only long enough to take the next chunk.
+        let next = receiver.lock().expect("Audit channel poisoned").recv();
+        let chunk = match next {
+            Ok(chunk) => chunk,
+            Err(_) => return summary,
+        };
+
+        for (line, text) in chunk {
+            if text.trim().is_empty() {
+                continue;
+            }
+
+            match parser.parse(&text) {
+                Ok(mut example) => {
+                    example.line = line;
+                    // Rules are only copied into the failures kept as samples.
+                    let passes = match rules {
+                        Some(rules) => rules.iter().all(|rule| rule.evalute(&example.password)),
+                        None => example.passes(),
+                    };
+
+                    if passes {
+                        summary.good += 1;
+                    } else {
+                        summary.failed += 1;
+                        if summary.failures.len() < sample_size {
+                            if let Some(rules) = rules {
+                                example.policies = rules.to_vec();
+                            }
+                            summary.failures.push(example);
+                        }
+                    }
+                },
+                Err(reason) => {
+                    if options.mode == ParseMode::Strict {
+                        stop.store(true, Ordering::Relaxed);
+                    }
+                    summary.malformed += 1;
+                    if summary.issues.len() < sample_size {
+                        summary.issues.push(ParseIssue { line, text: text.trim_end().to_string(), reason });
+                    }
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PolicyKind;
+
+    #[test]
+    fn check_streaming_matches_batch() {
+        let mut database = String::new();
+        for idx in 0..2000 {
+            let min = idx % 7 + 1;
+            let password: String = (0..(idx % 13 + 1)).map(|offset| (b'a' + ((idx + offset) % 5) as u8) as char).collect();
+            if idx % 97 == 0 {
+                database.push_str("garbage\n");
+            } else if idx % 101 == 0 {
+                database.push('\n');
+            }
+            database.push_str(&format!("{}-{} {}: {}\n", min, min + idx % 4 + 1, (b'a' + (idx % 5) as u8) as char, password));
+        }
+
+        let parser = LineParser::new(PolicyKind::Toboggan, false);
+        let batch = Example::read_from(database.as_bytes(), &parser, ParseMode::Lenient).unwrap();
+        let good = batch.examples.iter().filter(|example| example.passes()).count();
+        let failed_lines: Vec<usize> = batch.examples.iter()
+            .filter(|example| !example.passes())
+            .map(|example| example.line)
+            .take(10)
+            .collect();
+
+        let mut options = AuditOptions { workers: 4, chunk_size: 64, sample_size: 10, mode: ParseMode::Lenient };
+        let summary = audit(database.as_bytes(), &parser, None, &options).unwrap();
+        assert_eq!(summary.good, good);
+        assert_eq!(summary.failed, batch.examples.len() - good);
+        assert_eq!(summary.malformed, batch.warnings.len());
+        let sampled: Vec<usize> = summary.failures.iter().map(|example| example.line).collect();
+        assert_eq!(sampled, failed_lines);
+        assert_eq!(summary.issues[0].line, 1);
+
+        let rules = [Rule::parse("length 3+").unwrap()];
+        let long_enough = batch.examples.iter().filter(|example| example.password.chars().count() >= 3).count();
+        let summary = audit(database.as_bytes(), &parser, Some(&rules), &options).unwrap();
+        assert_eq!(summary.good, long_enough);
+        assert!(summary.failures.iter().all(|example| example.policies.len() == 1 && !example.passes()));
+
+        options.mode = ParseMode::Strict;
+        match audit(database.as_bytes(), &parser, None, &options) {
+            Err(DatabaseError::Malformed(issues)) => assert!(!issues.is_empty() && issues.iter().all(|issue| issue.text == "garbage")),
+            _ => panic!("A strict audit should fail on malformed lines"),
+        }
+        let clean: String = database.lines().filter(|line| *line != "garbage").map(|line| format!("{}\n", line)).collect();
+        assert_eq!(audit(clean.as_bytes(), &parser, None, &options).unwrap().malformed, 0);
+    }
+}
@@ -0,0 +1,59 @@
+use std::thread;
+
+use crate::{ride, Map, MapError, MovePolicy, Step, TreeCountingMovePolicy};
+
+/// Rides every slope over the shared map, splitting the slopes between up
+/// to `workers` scoped threads.  Each thread takes a contiguous run of
+/// slopes, so joining them in turn gives the results in input order.
+pub fn evaluate(map: &Map, slopes: &[Step], workers: usize) -> Vec<Result<i32, MapError>> {
+    if slopes.is_empty() {
+        return vec![];
+    }
+    let chunk_size = slopes.len().div_ceil(workers.max(1));
+
+    thread::scope(|scope| {
+        let handles: Vec<_> = slopes.chunks(chunk_size)
+            .map(|chunk| scope.spawn(move || chunk.iter().map(|slope| trees_on(map, *slope)).collect::<Vec<_>>()))
+            .collect();
+        handles.into_iter()
+            .flat_map(|handle| handle.join().expect("Slope worker panicked"))
+            .collect()
+    })
+}
+
+fn trees_on(map: &Map, slope: Step) -> Result<i32, MapError> {
+    let mut policy = TreeCountingMovePolicy::new(slope.0, slope.1);
+    ride(&mut policy, map)?;
+    Ok(policy.trees_hit())
+}
+
+/// Multiplies the tree counts, or `None` if the product overflows.
+pub fn product(counts: &[i32]) -> Option<u64> {
+    counts.iter().try_fold(1u64, |total, count| total.checked_mul(*count as u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Legend;
+
+    #[test]
+    fn check_batch_matches_sequential() {
+        let rows: Vec<String> = (0..200)
+            .map(|row: usize| (0..37).map(|col| if (row * 7 + col * 3).is_multiple_of(5) { '#' } else { '.' }).collect())
+            .collect();
+        let map = Map::from_rows(Legend::new(), rows).unwrap();
+
+        let slopes: Vec<Step> = (1..5).flat_map(|down| (-20..20).map(move |across| (across, down))).collect();
+        let sequential: Vec<i32> = slopes.iter().map(|slope| trees_on(&map, *slope).unwrap()).collect();
+        for workers in [1, 3, 8, 1000] {
+            let batch: Vec<i32> = evaluate(&map, &slopes, workers).into_iter().map(Result::unwrap).collect();
+            assert_eq!(batch, sequential);
+        }
+        assert!(evaluate(&map, &[], 4).is_empty());
+
+        assert_eq!(product(&[2, 7, 3, 4, 2]), Some(336));
+        assert_eq!(product(&[]), Some(1));
+        assert_eq!(product(&[i32::MAX, i32::MAX, i32::MAX]), None);
+    }
+}
@@ -1,6 +1,9 @@
 use std::{fmt::Debug, fs::File};
 use std::io::{BufReader, prelude::*};
 
+mod batch;
+mod render;
+
 /// Identifies the contents of the square
 #[derive(Copy, Clone, PartialEq)]
 enum Square {
@@ -17,8 +20,82 @@ impl Debug for Square {
     }
 }
 
+/// A move a policy made: the step it took and where the map's edges left it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Move {
+    step: Step,
+    to: (i32, i32),
+}
+
 trait MovePolicy {
-    fn next_move(&mut self, map: &Map, location: (i32, i32)) -> Result<(i32, i32), &'static str>;
+    fn next_move(&mut self, map: &Map, location: (i32, i32)) -> Result<Move, MapError>;
+
+    /// Whether the ride is over at this location.  By default it ends on the
+    /// bottom row.
+    fn finished(&self, map: &Map, location: (i32, i32)) -> bool {
+        location.1 as usize + 1 >= map.bounds().1
+    }
+
+    fn trees_hit(&self) -> i32;
+
+    fn describe(&self) -> String;
+}
+
+/// A move of the toboggan: so many squares across (negative is left) and
+/// so many down.
+type Step = (i32, i32);
+
+/// Reads a move written as `across,down`, such as `3,1`.  The toboggan only
+/// goes downhill, so `down` must be at least 1.
+fn parse_step(text: &str) -> Result<Step, String> {
+    let mut parts = text.trim().split(',');
+    let across = parts.next().and_then(|part| part.trim().parse().ok());
+    let down = parts.next().and_then(|part| part.trim().parse().ok());
+    match (across, down, parts.next()) {
+        (Some(across), Some(down), None) if down >= 1 => Ok((across, down)),
+        (Some(_), Some(_), None) => Err(format!("'{}' must move down at least one row", text.trim())),
+        _ => Err(format!("'{}' is not a move like 3,1", text.trim())),
+    }
+}
+
+/// Whether a move from the location stays on the map, neither sliding off
+/// the bottom nor, with a boundary that stops, leaving by a side.
+fn stays_on_map(map: &Map, location: (i32, i32), step: Step) -> bool {
+    let row = location.1 as i64 + step.1 as i64;
+    (0..map.bounds().1 as i64).contains(&row) && map.move_with_wrap(location, step.0, step.1).is_ok()
+}
+
+/// The locations visited on a ride, in order.
+type Route = Vec<(i32, i32)>;
+
+/// A finished ride.  `columns` holds how far across each location is when
+/// the map is repeated left and right instead of wrapped, so a step wider
+/// than the map still moves the whole step.
+struct Ride {
+    route: Route,
+    columns: Vec<i64>,
+}
+
+/// Rides from the top-left corner until the policy says the ride is over,
+/// returning every location visited, starting with the corner.
+fn ride(policy: &mut dyn MovePolicy, map: &Map) -> Result<Ride, MapError> {
+    let mut result = Ride { route: vec![(0, 0)], columns: vec![0] };
+    let mut location = (0, 0);
+    while !policy.finished(map, location) {
+        let next = match policy.next_move(map, location) {
+            Ok(next) => next,
+            Err(MapError::OffMap) => break,
+            Err(error) => return Err(error),
+        };
+        let column = match result.columns.last() {
+            Some(column) if map.boundary.wraps_horizontally() => column + next.step.0 as i64,
+            _ => next.to.0 as i64,
+        };
+        location = next.to;
+        result.route.push(location);
+        result.columns.push(column);
+    }
+    Ok(result)
 }
 
 struct TreeCountingMovePolicy {
@@ -38,70 +115,645 @@ impl TreeCountingMovePolicy {
 }
 
 impl MovePolicy for TreeCountingMovePolicy {
-    fn next_move(&mut self, map: &Map, location: (i32, i32)) -> Result<(i32, i32),&'static str> {
+    fn next_move(&mut self, map: &Map, location: (i32, i32)) -> Result<Move, MapError> {
         let new_location = map.move_with_wrap(location, self.across, self.down)?;
         if map.sense_location(new_location)? == Square::Tree {
             self.tree_count += 1;
         }
-        Ok(new_location)
+        Ok(Move { step: (self.across, self.down), to: new_location })
+    }
+
+    fn finished(&self, map: &Map, location: (i32, i32)) -> bool {
+        !stays_on_map(map, location, (self.across, self.down))
+    }
+
+    fn trees_hit(&self) -> i32 {
+        self.tree_count
+    }
+
+    fn describe(&self) -> String {
+        format!("slope {},{}", self.across, self.down)
+    }
+}
+
+/// Looks one move ahead and takes the first allowed move that lands on open
+/// snow, or the first allowed move if every one hits a tree.
+struct GreedyMovePolicy {
+    tree_count: i32,
+    moves: Vec<Step>,
+}
+
+impl GreedyMovePolicy {
+    fn new(moves: Vec<Step>) -> Self {
+        GreedyMovePolicy {
+            tree_count: 0,
+            moves,
+        }
+    }
+}
+
+impl MovePolicy for GreedyMovePolicy {
+    fn next_move(&mut self, map: &Map, location: (i32, i32)) -> Result<Move, MapError> {
+        let mut fallback = None;
+        for step in self.moves.iter().filter(|step| stays_on_map(map, location, **step)) {
+            let candidate = Move { step: *step, to: map.move_with_wrap(location, step.0, step.1)? };
+            if map.sense_location(candidate.to)? == Square::Open {
+                return Ok(candidate);
+            }
+            fallback = fallback.or(Some(candidate));
+        }
+
+        let new_move = fallback.ok_or(MapError::Stuck("No move stays on the map"))?;
+        self.tree_count += 1;
+        Ok(new_move)
+    }
+
+    fn finished(&self, map: &Map, location: (i32, i32)) -> bool {
+        !self.moves.iter().any(|step| stays_on_map(map, location, *step))
+    }
+
+    fn trees_hit(&self) -> i32 {
+        self.tree_count
+    }
+
+    fn describe(&self) -> String {
+        format!("greedy over {}", describe_steps(&self.moves))
+    }
+}
+
+/// SplitMix64, so a seed always gives the same walk.
+struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Rng { state: seed }
+    }
+
+    fn below(&mut self, bound: usize) -> usize {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        ((z ^ (z >> 31)) % bound as u64) as usize
+    }
+}
+
+/// Picks one of the allowed moves at random at every step.
+struct RandomWalkMovePolicy {
+    tree_count: i32,
+    moves: Vec<Step>,
+    seed: u64,
+    rng: Rng,
+}
+
+impl RandomWalkMovePolicy {
+    fn new(moves: Vec<Step>, seed: u64) -> Self {
+        RandomWalkMovePolicy {
+            tree_count: 0,
+            moves,
+            seed,
+            rng: Rng::new(seed),
+        }
+    }
+}
+
+impl MovePolicy for RandomWalkMovePolicy {
+    fn next_move(&mut self, map: &Map, location: (i32, i32)) -> Result<Move, MapError> {
+        let allowed: Vec<Step> = self.moves.iter()
+            .filter(|step| stays_on_map(map, location, **step))
+            .copied()
+            .collect();
+        if allowed.is_empty() {
+            return Err(MapError::Stuck("No move stays on the map"));
+        }
+
+        let step = allowed[self.rng.below(allowed.len())];
+        let new_location = map.move_with_wrap(location, step.0, step.1)?;
+        if map.sense_location(new_location)? == Square::Tree {
+            self.tree_count += 1;
+        }
+        Ok(Move { step, to: new_location })
+    }
+
+    fn finished(&self, map: &Map, location: (i32, i32)) -> bool {
+        !self.moves.iter().any(|step| stays_on_map(map, location, *step))
+    }
+
+    fn trees_hit(&self) -> i32 {
+        self.tree_count
+    }
+
+    fn describe(&self) -> String {
+        format!("random walk over {} with seed {}", describe_steps(&self.moves), self.seed)
+    }
+}
+
+/// Follows a fixed list of moves, stopping when the list runs out or the
+/// next move would leave the bottom of the map.
+struct ScriptedMovePolicy {
+    tree_count: i32,
+    moves: Vec<Step>,
+    next: usize,
+}
+
+impl ScriptedMovePolicy {
+    fn new(moves: Vec<Step>) -> Self {
+        ScriptedMovePolicy {
+            tree_count: 0,
+            moves,
+            next: 0,
+        }
+    }
+
+    /// Reads a script of moves, one `across,down` per line.  Blank lines and
+    /// lines starting with `#` are skipped.
+    fn read(path: &str) -> Result<Self, String> {
+        let file = File::open(path).map_err(|error| format!("{}: {}", path, error))?;
+        let mut moves: Vec<Step> = vec![];
+        for (idx, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.map_err(|error| format!("{}: {}", path, error))?;
+            if line.trim().is_empty() || line.trim().starts_with('#') {
+                continue;
+            }
+            moves.push(parse_step(&line).map_err(|error| format!("{} line {}: {}", path, idx + 1, error))?);
+        }
+        Ok(ScriptedMovePolicy::new(moves))
+    }
+}
+
+impl MovePolicy for ScriptedMovePolicy {
+    fn next_move(&mut self, map: &Map, location: (i32, i32)) -> Result<Move, MapError> {
+        let step = *self.moves.get(self.next).ok_or(MapError::Stuck("The script has run out of moves"))?;
+        self.next += 1;
+
+        let new_location = map.move_with_wrap(location, step.0, step.1)?;
+        if map.sense_location(new_location)? == Square::Tree {
+            self.tree_count += 1;
+        }
+        Ok(Move { step, to: new_location })
+    }
+
+    fn finished(&self, map: &Map, location: (i32, i32)) -> bool {
+        match self.moves.get(self.next) {
+            Some(step) => !stays_on_map(map, location, *step),
+            None => true,
+        }
+    }
+
+    fn trees_hit(&self) -> i32 {
+        self.tree_count
+    }
+
+    fn describe(&self) -> String {
+        format!("script of {} moves ({} taken)", self.moves.len(), self.next)
+    }
+}
+
+/// Rides the route hitting the fewest trees, where each move goes down one
+/// row and across by at most `max_shift` either way, wrapping horizontally.
+/// The route is planned from wherever the first move starts.
+struct LeastTreesMovePolicy {
+    tree_count: i32,
+    max_shift: i32,
+    route: Vec<(i32, i32)>,
+    shifts: Vec<i32>,
+    next: usize,
+}
+
+impl LeastTreesMovePolicy {
+    fn new(max_shift: i32) -> Self {
+        LeastTreesMovePolicy {
+            tree_count: 0,
+            max_shift,
+            route: vec![],
+            shifts: vec![],
+            next: 0,
+        }
+    }
+}
+
+impl MovePolicy for LeastTreesMovePolicy {
+    fn next_move(&mut self, map: &Map, location: (i32, i32)) -> Result<Move, MapError> {
+        if self.route.is_empty() {
+            let (route, shifts, _) = map.least_trees_path(location, self.max_shift)?;
+            self.route = route;
+            self.shifts = shifts;
+            self.next = 1;
+        }
+
+        let new_location = *self.route.get(self.next).ok_or(MapError::Stuck("The planned route has ended"))?;
+        let step = (self.shifts[self.next - 1], 1);
+        self.next += 1;
+        if map.sense_location(new_location)? == Square::Tree {
+            self.tree_count += 1;
+        }
+        Ok(Move { step, to: new_location })
+    }
+
+    fn trees_hit(&self) -> i32 {
+        self.tree_count
+    }
+
+    fn describe(&self) -> String {
+        format!("least trees shifting at most {}", self.max_shift)
+    }
+}
+
+fn describe_steps(steps: &[Step]) -> String {
+    let parts: Vec<String> = steps.iter().map(|step| format!("{},{}", step.0, step.1)).collect();
+    parts.join(" ")
+}
+
+/// What can go wrong loading a map or moving around it.
+#[derive(Debug)]
+enum MapError {
+    Io(std::io::Error),
+    EmptyMap,
+    /// A row whose width differs from the first row's.  `line` counts from 1.
+    RaggedRow { line: usize, expected: usize, found: usize },
+    UnknownTerrain { line: usize, column: usize, symbol: char },
+    OutOfBounds { x: i32, y: i32 },
+    /// A move that leaves the map.  Rides treat it as the end of the ride
+    /// rather than a failure.
+    OffMap,
+    /// A policy or planner with nowhere to go.
+    Stuck(&'static str),
+}
+
+impl std::fmt::Display for MapError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MapError::Io(error) => write!(f, "{}", error),
+            MapError::EmptyMap => f.write_str("The map is empty"),
+            MapError::RaggedRow { line, expected, found } =>
+                write!(f, "Line {} is {} squares wide, expected {}", line, found, expected),
+            MapError::UnknownTerrain { line, column, symbol } =>
+                write!(f, "Unknown terrain '{}' on line {} column {}", symbol, line, column),
+            MapError::OutOfBounds { x, y } => write!(f, "{},{} is out of bounds", x, y),
+            MapError::OffMap => f.write_str("Off the map"),
+            MapError::Stuck(reason) => f.write_str(reason),
+        }
+    }
+}
+
+impl From<std::io::Error> for MapError {
+    fn from(error: std::io::Error) -> Self {
+        MapError::Io(error)
+    }
+}
+
+/// What happens when a move crosses the edge of the map.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Boundary {
+    /// Wrap left and right; going past the bottom or top leaves the map.
+    /// This is how the puzzle's map repeats.
+    WrapHorizontal,
+    /// Wrap in both directions.
+    Torus,
+    /// Stay on the last square before the edge.
+    Clamp,
+    /// Bounce back off the edge by the distance overshot.
+    Reflect,
+    /// Any move past an edge leaves the map.
+    Stop,
+}
+
+impl Boundary {
+    /// Brings a coordinate back onto an axis of `size` squares, or `None` if
+    /// it has left the map.  `wrap` says whether this axis wraps.
+    fn resolve(&self, position: i64, size: usize, wrap: bool) -> Option<i32> {
+        let size = size as i64;
+        if (0..size).contains(&position) {
+            return Some(position as i32);
+        }
+
+        let resolved = match self {
+            _ if wrap => position.rem_euclid(size),
+            Boundary::Clamp => position.clamp(0, size - 1),
+            Boundary::Reflect if size == 1 => 0,
+            Boundary::Reflect => {
+                // Reflecting repeats with period 2(size - 1): 0, 1, .., size-1, size-2, .., 1.
+                let period = 2 * (size - 1);
+                let folded = position.rem_euclid(period);
+                if folded < size { folded } else { period - folded }
+            },
+            _ => return None,
+        };
+        Some(resolved as i32)
+    }
+
+    /// Whether the map repeats left and right, so a slope's column can be
+    /// worked out without stepping.
+    fn wraps_horizontally(&self) -> bool {
+        matches!(self, Boundary::WrapHorizontal | Boundary::Torus)
+    }
+}
+
+impl std::str::FromStr for Boundary {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "wrap" => Ok(Boundary::WrapHorizontal),
+            "torus" => Ok(Boundary::Torus),
+            "clamp" => Ok(Boundary::Clamp),
+            "reflect" => Ok(Boundary::Reflect),
+            "stop" => Ok(Boundary::Stop),
+            _ => Err(format!("Unknown boundary '{}', expected wrap, torus, clamp, reflect or stop", s)),
+        }
+    }
+}
+
+/// A kind of ground the map can show.  `square` says whether the policies
+/// treat it as a tree to avoid or as open snow.
+#[derive(Clone)]
+struct Terrain {
+    symbol: char,
+    name: String,
+    cost: u32,
+    square: Square,
+}
+
+/// The characters a map may use and what each one stands for.
+#[derive(Clone)]
+struct Legend {
+    terrains: Vec<Terrain>,
+}
+
+impl Legend {
+    /// The puzzle's legend: `#` is a tree costing 1 and `.` is open snow.
+    fn new() -> Self {
+        Legend {
+            terrains: vec![
+                Terrain { symbol: '#', name: String::from("tree"), cost: 1, square: Square::Tree },
+                Terrain { symbol: '.', name: String::from("open"), cost: 0, square: Square::Open },
+            ],
+        }
+    }
+
+    /// Reads a legend with one terrain per line: the symbol, a name, the
+    /// cost of landing on it, and `tree` if policies should avoid it.
+    ///
+    /// ```text
+    /// # tree 10 tree
+    /// ^ rock 5 tree
+    /// ~ ice 1
+    /// . snow 0
+    /// ```
+    fn read(path: &str) -> Result<Self, String> {
+        let file = File::open(path).map_err(|error| format!("{}: {}", path, error))?;
+        let mut legend = Legend { terrains: vec![] };
+
+        for (idx, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.map_err(|error| format!("{}: {}", path, error))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let bad = |reason: &str| format!("{} line {}: {}", path, idx + 1, reason);
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            let mut symbol = parts[0].chars();
+            let symbol = match (symbol.next(), symbol.next()) {
+                (Some(symbol), None) => symbol,
+                _ => return Err(bad("the symbol must be a single character")),
+            };
+            if legend.find(symbol).is_some() {
+                return Err(bad(&format!("'{}' is already in the legend", symbol)));
+            }
+            let name = parts.get(1).ok_or_else(|| bad("expected 'symbol name cost [tree]'"))?;
+            let cost = parts.get(2).and_then(|cost| cost.parse().ok()).ok_or_else(|| bad("the cost must be a whole number"))?;
+            let square = match parts.get(3..) {
+                Some([]) => Square::Open,
+                Some(["tree"]) => Square::Tree,
+                _ => return Err(bad("only 'tree' may follow the cost")),
+            };
+
+            legend.terrains.push(Terrain { symbol, name: name.to_string(), cost, square });
+        }
+
+        if legend.terrains.is_empty() {
+            return Err(format!("{} has no terrains", path));
+        }
+        Ok(legend)
+    }
+
+    fn find(&self, symbol: char) -> Option<usize> {
+        self.terrains.iter().position(|terrain| terrain.symbol == symbol)
     }
 }
 
+/// How often a ride landed on each terrain, in legend order, and what it
+/// cost in total.
+struct TerrainTally {
+    hits: Vec<usize>,
+    cost: u64,
+}
 
 struct Map {
     data: Vec<Vec<Square>>,
+    /// The legend entry for every square.
+    terrain: Vec<Vec<usize>>,
+    legend: Legend,
+    boundary: Boundary,
 }
 
 impl Map {
-    fn new() -> Self {
-        Map {
+    /// Builds a map from its rows, which must all be the same, non-zero
+    /// width and use only characters the legend knows.
+    fn from_rows<I, S>(legend: Legend, rows: I) -> Result<Self, MapError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut map = Map {
             data: vec![],
+            terrain: vec![],
+            legend,
+            boundary: Boundary::WrapHorizontal,
+        };
+        for row in rows {
+            map.append_row(row.as_ref())?;
+        }
+
+        if map.data.is_empty() {
+            return Err(MapError::EmptyMap);
         }
+        Ok(map)
+    }
+
+    fn read(path: &str, legend: Legend) -> Result<Self, MapError> {
+        let reader = BufReader::new(File::open(path)?);
+        let rows = reader.lines().collect::<Result<Vec<String>, _>>()?;
+        Map::from_rows(legend, rows)
     }
 
-    fn append_row(&mut self, row: &str) {
+    fn append_row(&mut self, row: &str) -> Result<(), MapError> {
+        let line = self.data.len() + 1;
         let mut new_row: Vec<Square> = vec![];
-        for char in row.chars() {
-            match char {
-                '#' => new_row.push(Square::Tree),
-                _ => new_row.push(Square::Open),
-            }
+        let mut new_terrain: Vec<usize> = vec![];
+        for (column, symbol) in row.chars().enumerate() {
+            let terrain = self.legend.find(symbol)
+                .ok_or(MapError::UnknownTerrain { line, column: column + 1, symbol })?;
+            new_row.push(self.legend.terrains[terrain].square);
+            new_terrain.push(terrain);
+        }
+
+        match self.data.first() {
+            None if new_row.is_empty() => return Err(MapError::EmptyMap),
+            Some(first) if first.len() != new_row.len() => {
+                return Err(MapError::RaggedRow { line, expected: first.len(), found: new_row.len() });
+            },
+            _ => {},
         }
 
         self.data.push(new_row);
+        self.terrain.push(new_terrain);
+        Ok(())
+    }
+
+    /// Counts the terrain landed on along a route, leaving out the square it
+    /// starts on.
+    fn tally(&self, route: &[(i32, i32)]) -> TerrainTally {
+        let mut tally = TerrainTally { hits: vec![0; self.legend.terrains.len()], cost: 0 };
+        for location in route.iter().skip(1) {
+            let terrain = self.terrain[location.1 as usize][location.0 as usize];
+            tally.hits[terrain] += 1;
+            tally.cost += self.legend.terrains[terrain].cost as u64;
+        }
+        tally
+    }
+
+    fn describe_tally(&self, tally: &TerrainTally) -> String {
+        let hits: Vec<String> = self.legend.terrains.iter().zip(&tally.hits)
+            .map(|(terrain, hits)| format!("{} {}", terrain.name, hits))
+            .collect();
+        format!("cost {}: {}", tally.cost, hits.join(", "))
     }
 
     fn bounds(&self) -> (usize, usize) {
-        (self.data[0].len(), self.data.len())
+        (self.data.first().map_or(0, |row| row.len()), self.data.len())
+    }
+
+    /// Moves by any number of squares, handling the edges as the map's
+    /// boundary mode says.  A move that leaves the map fails with `OffMap`.
+    fn move_with_wrap(&self, location: (i32, i32), across: i32, down: i32) -> Result<(i32, i32), MapError> {
+        let (width, height) = self.bounds();
+        let (wrap_across, wrap_down) = match self.boundary {
+            Boundary::Torus => (true, true),
+            Boundary::WrapHorizontal => (true, false),
+            _ => (false, false),
+        };
+
+        let horizontal = self.boundary.resolve(location.0 as i64 + across as i64, width, wrap_across);
+        let vertical = self.boundary.resolve(location.1 as i64 + down as i64, height, wrap_down);
+        match (horizontal, vertical) {
+            (Some(horizontal), Some(vertical)) => Ok((horizontal, vertical)),
+            _ => Err(MapError::OffMap),
+        }
     }
 
-    fn move_with_wrap(&self, location: (i32, i32), across: i32, down: i32) -> Result<(i32, i32), &'static str> {
-        if across > (self.data[0].len() as i32) || (down > self.data.len() as i32) {
-            return Err("Out of bounds");
+    /// Counts the trees hit riding a fixed slope from the top-left corner to
+    /// the bottom.  When the map wraps horizontally each landing square is
+    /// worked out directly; otherwise the slope is stepped with
+    /// `move_with_wrap`.  Either way there is no ride through `next_move`.
+    fn trees_on_slope(&self, across: i32, down: i32) -> usize {
+        let (width, height) = self.bounds();
+        let down = down.max(1);
+
+        if self.boundary.wraps_horizontally() {
+            let width = width as i64;
+            return (1..)
+                .map(|step: usize| (step, step * down as usize))
+                .take_while(|(_, row)| *row < height)
+                .filter(|(step, row)| {
+                    let column = (*step as i64 * across as i64).rem_euclid(width) as usize;
+                    self.data[*row][column] == Square::Tree
+                })
+                .count();
         }
 
-        let new_horizontal = if location.0 + across >= (self.data[0].len() as i32) {
-            location.0 + across - (self.data[0].len() as i32)
-        } else if location.0 + across < 0 {
-            location.0 + across + (self.data[0].len() as i32)
-        } else {
-            location.0 + across
-        };
+        let mut trees = 0;
+        let mut location = (0, 0);
+        while ((location.1 as i64 + down as i64) as usize) < height {
+            location = match self.move_with_wrap(location, across, down) {
+                Ok(location) => location,
+                Err(_) => break,
+            };
+            if self.data[location.1 as usize][location.0 as usize] == Square::Tree {
+                trees += 1;
+            }
+        }
+        trees
+    }
 
-        let new_vertical = if location.1 + down >= (self.data.len() as i32) {
-            location.1 + down - (self.data.len() as i32)
-        } else if location.1 + down < 0 {
-            location.1 + down + (self.data.len() as i32)
-        } else {
-            location.1 + down
-        };
+    /// Finds the route from `start` to the bottom row hitting the fewest
+    /// trees, moving down one row per step and across by at most `max_shift`,
+    /// with the map's boundary mode at the edges.  Returns the route, starting with `start`, the
+    /// shift taken by each move, and the trees hit after leaving the start.
+    fn least_trees_path(&self, start: (i32, i32), max_shift: i32) -> Result<(Route, Vec<i32>, usize), MapError> {
+        let (width, height) = self.bounds();
+        self.sense_location(start)?;
+        if max_shift < 0 {
+            return Err(MapError::Stuck("The shift cannot be negative"));
+        }
+
+        // Shifts past the width reach no new columns however the edges behave.
+        let reach = (max_shift as usize).min(width) as i32;
+        let first_row = start.1 as usize;
+
+        // trees[col] is the fewest trees hit reaching `col` on the current
+        // row; came_from[row][col] is the column on the row above and the
+        // shift that moved from it.
+        let mut trees: Vec<Option<usize>> = vec![None; width];
+        trees[start.0 as usize] = Some(0);
+        let mut came_from: Vec<Vec<(usize, i32)>> = vec![vec![(0, 0); width]; height];
+
+        for (row, came_from_row) in came_from.iter_mut().enumerate().skip(first_row + 1) {
+            let mut next: Vec<Option<usize>> = vec![None; width];
+            for (from, count) in trees.iter().enumerate() {
+                let count = match count {
+                    Some(count) => *count,
+                    None => continue,
+                };
+                for shift in -reach..=reach {
+                    let to = match self.move_with_wrap((from as i32, row as i32 - 1), shift, 1) {
+                        Ok(location) => location.0 as usize,
+                        Err(_) => continue,
+                    };
+                    let count = count + if self.data[row][to] == Square::Tree { 1 } else { 0 };
+                    if next[to].is_none_or(|best| count < best) {
+                        next[to] = Some(count);
+                        came_from_row[to] = (from, shift);
+                    }
+                }
+            }
+            trees = next;
+        }
+
+        let (mut col, total) = trees.iter().enumerate()
+            .filter_map(|(col, count)| count.map(|count| (col, count)))
+            .min_by_key(|(_, count)| *count)
+            .ok_or(MapError::Stuck("No route reaches the bottom"))?;
 
-        Ok((new_horizontal, new_vertical))
+        let mut route = vec![(col as i32, height as i32 - 1)];
+        let mut shifts: Vec<i32> = vec![];
+        for row in (first_row + 1..height).rev() {
+            let (from, shift) = came_from[row][col];
+            col = from;
+            route.push((col as i32, row as i32 - 1));
+            shifts.push(shift);
+        }
+        route.reverse();
+        shifts.reverse();
+        Ok((route, shifts, total))
     }
 
-    fn sense_location(&self, location: (i32, i32)) -> Result<Square, &'static str> {
-        if location.0 > (self.data[0].len() as i32) || location.1 > (self.data.len() as i32) {
-            Err("Out of bounds")
+    fn sense_location(&self, location: (i32, i32)) -> Result<Square, MapError> {
+        let (width, height) = self.bounds();
+        if location.0 < 0 || location.1 < 0 || location.0 as usize >= width || location.1 as usize >= height {
+            Err(MapError::OutOfBounds { x: location.0, y: location.1 })
         } else {
             Ok(self.data[location.1 as usize][location.0 as usize])
         }
@@ -109,48 +761,642 @@ impl Map {
 }
 
 
-fn main() {
-    let mut tobogan_map = Map::new();
-
-    match File::open("./input.txt") {
-        Ok(file) => {
-            let reader = BufReader::new(file);
-            
-            for line in reader.lines() {
-                if let Ok(the_line) = line {
-                    tobogan_map.append_row(&the_line);
-                }
-            }
+/// Trees hit on one slope.
+struct SlopeCount {
+    slope: Step,
+    trees: usize,
+}
+
+/// Counts the trees on every slope with `across` and `down` in the given
+/// inclusive ranges.
+fn search_slopes(map: &Map, across: (i32, i32), down: (i32, i32)) -> Vec<SlopeCount> {
+    let mut result: Vec<SlopeCount> = vec![];
+    for down in down.0.max(1)..=down.1 {
+        for across in across.0..=across.1 {
+            result.push(SlopeCount { slope: (across, down), trees: map.trees_on_slope(across, down) });
+        }
+    }
+    result
+}
+
+/// Reads an inclusive range written `min..max`.
+fn parse_range(text: &str) -> Result<(i32, i32), String> {
+    let bad = || format!("'{}' is not a range like 1..7", text);
+    let (min, max) = text.split_once("..").ok_or_else(bad)?;
+    let (min, max): (i32, i32) = (min.parse().map_err(|_| bad())?, max.parse().map_err(|_| bad())?);
+    if min > max {
+        return Err(format!("Range {} has min above max", text));
+    }
+    Ok((min, max))
+}
+
+/// Runs `search [--across min..max] [--down min..max] [--product across,down ...]`.
+/// Across defaults to every distinct horizontal shift and down to every row.
+fn run_search(map: &Map, args: &[String]) -> Result<(), String> {
+    let (width, height) = map.bounds();
+    let mut across = (0, width as i32 - 1);
+    let mut down = (1, height as i32 - 1);
+    let mut product: Vec<Step> = vec![];
+
+    let mut idx = 0;
+    while idx < args.len() {
+        let value = args.get(idx + 1).ok_or_else(|| format!("{} needs a value", args[idx]));
+        match args[idx].as_str() {
+            "--across" => across = parse_range(value?)?,
+            "--down" => down = parse_range(value?)?,
+            "--product" => {
+                product = args[idx + 1..].iter().map(|text| parse_step(text)).collect::<Result<Vec<Step>, String>>()?;
+                idx = args.len();
+                continue;
+            },
+            other => return Err(format!("Unknown search option '{}'", other)),
+        }
+        idx += 2;
+    }
+
+    let counts = search_slopes(map, across, down);
+    let fewest = counts.iter().map(|count| count.trees).min().ok_or("The search ranges hold no slopes")?;
+    let most = counts.iter().map(|count| count.trees).max().unwrap_or(0);
+    let slopes_with = |trees: usize| -> String {
+        let slopes: Vec<Step> = counts.iter().filter(|count| count.trees == trees).map(|count| count.slope).collect();
+        let shown = describe_steps(&slopes[..slopes.len().min(10)]);
+        if slopes.len() > 10 {
+            format!("{} and {} more", shown, slopes.len() - 10)
+        } else {
+            shown
+        }
+    };
+
+    println!("Searched {} slopes", counts.len());
+    println!("Fewest trees: {} with slopes {}", fewest, slopes_with(fewest));
+    println!("Most trees: {} with slopes {}", most, slopes_with(most));
+
+    if !product.is_empty() {
+        let mut total: Option<u64> = Some(1);
+        for slope in &product {
+            let trees = map.trees_on_slope(slope.0, slope.1);
+            println!("You hit {} trees with slope {},{}", trees, slope.0, slope.1);
+            total = total.and_then(|total| total.checked_mul(trees as u64));
+        }
+        match total {
+            Some(total) => println!("The product is: {}", total),
+            None => println!("The product overflows"),
+        }
+    }
+    Ok(())
+}
+
+/// Rides many fixed slopes at once with `batch::evaluate`:
+///
+/// ```text
+/// day3 [options] batch [--workers n] [--across min..max --down min..max] [across,down ...]
+/// ```
+///
+/// The ranges and the listed slopes are ridden together; with neither, the
+/// five puzzle slopes are.
+fn run_batch(map: &Map, args: &[String]) -> Result<(), String> {
+    let mut workers = std::thread::available_parallelism().map(|count| count.get()).unwrap_or(4);
+    let mut across: Option<(i32, i32)> = None;
+    let mut down: Option<(i32, i32)> = None;
+    let mut slopes: Vec<Step> = vec![];
+
+    let mut idx = 0;
+    while idx < args.len() {
+        if !args[idx].starts_with("--") {
+            slopes.push(parse_step(&args[idx])?);
+            idx += 1;
+            continue;
+        }
+
+        let value = args.get(idx + 1).ok_or_else(|| format!("{} needs a value", args[idx]));
+        match args[idx].as_str() {
+            "--workers" => workers = value?.parse().ok().filter(|count| *count > 0).ok_or("--workers needs a number above 0")?,
+            "--across" => across = Some(parse_range(value?)?),
+            "--down" => down = Some(parse_range(value?)?),
+            other => return Err(format!("Unknown batch option '{}'", other)),
+        }
+        idx += 2;
+    }
+
+    if across.is_some() || down.is_some() {
+        let (across, down) = (across.unwrap_or((0, 7)), down.unwrap_or((1, 2)));
+        for down in down.0.max(1)..=down.1 {
+            slopes.extend((across.0..=across.1).map(|across| (across, down)));
+        }
+    }
+    if slopes.is_empty() {
+        slopes = vec![(1, 1), (3, 1), (5, 1), (7, 1), (1, 2)];
+    }
+
+    let mut counts: Vec<i32> = vec![];
+    for (slope, result) in slopes.iter().zip(batch::evaluate(map, &slopes, workers)) {
+        match result {
+            Ok(trees) => {
+                println!("You hit {} trees with slope {},{}", trees, slope.0, slope.1);
+                counts.push(trees);
+            },
+            Err(error) => println!("The slope {},{} ride failed: {}", slope.0, slope.1, error),
+        }
+    }
+    match batch::product(&counts) {
+        Some(total) => println!("The product is: {}", total),
+        None => println!("The product overflows"),
+    }
+    Ok(())
+}
+
+/// Builds the policies named on the command line:
+///
+/// ```text
+/// day3 [options] [slopes [across,down ...]]
+/// day3 [options] greedy across,down ...
+/// day3 [options] random [--seed n] across,down ...
+/// day3 [options] script moves.txt
+/// day3 [options] least [max_shift]
+/// ```
+///
+/// The options before the strategy are `--map file`, `--legend file`,
+/// `--boundary mode`, `--path`, which prints each ride's route, and
+/// `--render file.ppm`, which draws the routes over the map.
+///
+/// `search` and `batch` are handled separately by `run_search` and
+/// `run_batch`.
+fn policies_from_args(args: &[String]) -> Result<Vec<Box<dyn MovePolicy>>, String> {
+    let strategy = args.first().map(|arg| arg.as_str()).unwrap_or("slopes");
+    let rest = if args.is_empty() { args } else { &args[1..] };
+
+    let steps = |texts: &[String]| -> Result<Vec<Step>, String> {
+        let steps = texts.iter().map(|text| parse_step(text)).collect::<Result<Vec<Step>, String>>()?;
+        if steps.is_empty() {
+            return Err(format!("{} needs at least one move like 3,1", strategy));
+        }
+        Ok(steps)
+    };
+
+    match strategy {
+        "slopes" => {
+            let slopes = if rest.is_empty() {
+                vec![(1, 1), (3, 1), (5, 1), (7, 1), (1, 2)]
+            } else {
+                steps(rest)?
+            };
+            Ok(slopes.into_iter()
+                .map(|(across, down)| Box::new(TreeCountingMovePolicy::new(across, down)) as Box<dyn MovePolicy>)
+                .collect())
         },
-        Err(error) => println!("Failed to load data: {}", error),
+        "greedy" => Ok(vec![Box::new(GreedyMovePolicy::new(steps(rest)?))]),
+        "least" => {
+            let max_shift = match rest.first() {
+                Some(text) => text.parse().ok().filter(|shift| *shift >= 0).ok_or("least needs a shift of 0 or more")?,
+                None => 1,
+            };
+            Ok(vec![Box::new(LeastTreesMovePolicy::new(max_shift))])
+        },
+        "random" => {
+            let (seed, rest) = match rest.first().map(|arg| arg.as_str()) {
+                Some("--seed") => {
+                    let seed = rest.get(1).and_then(|text| text.parse().ok()).ok_or("--seed needs a number")?;
+                    (seed, &rest[2..])
+                },
+                _ => (2020, rest),
+            };
+            Ok(vec![Box::new(RandomWalkMovePolicy::new(steps(rest)?, seed))])
+        },
+        "script" => {
+            let path = rest.first().ok_or("script needs a file of moves")?;
+            Ok(vec![Box::new(ScriptedMovePolicy::read(path)?)])
+        },
+        other => Err(format!("Unknown strategy '{}', expected slopes, greedy, least, random or script", other)),
     }
+}
 
+fn main() {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let mut map_path = String::from("./input.txt");
+    let mut show_path = false;
+    let mut boundary = Boundary::WrapHorizontal;
+    let mut render_path: Option<String> = None;
+    let mut legend_path: Option<String> = None;
+    while args.first().is_some_and(|arg| arg.starts_with("--")) {
+        let option = args.remove(0);
+        match option.as_str() {
+            "--path" => show_path = true,
+            "--map" | "--boundary" | "--render" | "--legend" if args.is_empty() => {
+                println!("{} needs a value", option);
+                return;
+            },
+            "--map" => map_path = args.remove(0),
+            "--render" => render_path = Some(args.remove(0)),
+            "--legend" => legend_path = Some(args.remove(0)),
+            "--boundary" => match args.remove(0).parse() {
+                Ok(mode) => boundary = mode,
+                Err(error) => {
+                    println!("{}", error);
+                    return;
+                },
+            },
+            _ => {
+                println!("Unknown option '{}'", option);
+                return;
+            },
+        }
+    }
 
-    let policies: Vec<TreeCountingMovePolicy> = vec![
-        TreeCountingMovePolicy::new(1, 1),
-        TreeCountingMovePolicy::new(3, 1),
-        TreeCountingMovePolicy::new(5, 1),
-        TreeCountingMovePolicy::new(7, 1),
-        TreeCountingMovePolicy::new(1, 2),
-    ];
+    let legend = match legend_path {
+        Some(path) => match Legend::read(&path) {
+            Ok(legend) => legend,
+            Err(error) => {
+                println!("{}", error);
+                return;
+            },
+        },
+        None => Legend::new(),
+    };
+    let mut tobogan_map = match Map::read(&map_path, legend) {
+        Ok(map) => map,
+        Err(error) => {
+            println!("Failed to load {}: {}", map_path, error);
+            return;
+        },
+    };
+    tobogan_map.boundary = boundary;
+
+    let command = match args.first().map(|arg| arg.as_str()) {
+        Some("search") => Some(run_search as fn(&Map, &[String]) -> Result<(), String>),
+        Some("batch") => Some(run_batch as fn(&Map, &[String]) -> Result<(), String>),
+        _ => None,
+    };
+    if let Some(command) = command {
+        if let Err(error) = command(&tobogan_map, &args[1..]) {
+            println!("{}", error);
+        }
+        return;
+    }
+
+    let policies = match policies_from_args(&args) {
+        Ok(policies) => policies,
+        Err(error) => {
+            println!("{}", error);
+            return;
+        },
+    };
 
     let mut trees_hit: Vec<i32> = vec![];
+    let mut rides: Vec<Ride> = vec![];
     for mut policy in policies {
-        let mut current_location = (0, 0);
-        let mut idx = 0;
-        while idx < tobogan_map.bounds().1 {
-            current_location = policy.next_move(&tobogan_map, current_location).unwrap();
-            idx += policy.down as usize;
+        let finished = match ride(policy.as_mut(), &tobogan_map) {
+            Ok(finished) => finished,
+            Err(error) => {
+                println!("The {} ride failed: {}", policy.describe(), error);
+                continue;
+            },
+        };
+
+        trees_hit.push(policy.trees_hit());
+        println!("You hit {} trees with policy {} ({})", policy.trees_hit(), policy.describe(),
+            tobogan_map.describe_tally(&tobogan_map.tally(&finished.route)));
+        if show_path {
+            let route: Vec<String> = finished.route.iter().map(|location| format!("{},{}", location.0, location.1)).collect();
+            println!("    {}", route.join(" "));
+        }
+        rides.push(finished);
+    }
+
+    if let Some(render_path) = render_path {
+        let written = File::create(&render_path)
+            .and_then(|file| render::write_ppm(&mut std::io::BufWriter::new(file), &tobogan_map, &rides, 4));
+        match written {
+            Ok(_) => println!("Wrote {}", render_path),
+            Err(error) => println!("Failed to write {}: {}", render_path, error),
+        }
+    }
+
+    if trees_hit.len() > 1 {
+        match batch::product(&trees_hit) {
+            Some(total) => println!("The product is: {}", total),
+            None => println!("The product overflows"),
         }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        trees_hit.push(policy.tree_count);
-        println!("You hit {} trees with policy {},{}", policy.tree_count, policy.across, policy.down);
+    pub(crate) const EXAMPLE: [&str; 11] = [
+        "..##.......",
+        "#...#...#..",
+        ".#....#..#.",
+        "..#.#...#.#",
+        ".#...##..#.",
+        "..#.##.....",
+        ".#.#.#....#",
+        ".#........#",
+        "#.##...#...",
+        "#...##....#",
+        ".#..#...#.#",
+    ];
+
+    pub(crate) fn example_map() -> Map {
+        Map::from_rows(Legend::new(), EXAMPLE.iter()).expect("The example map is valid")
     }
 
-    let mut prod = trees_hit[0] as i64;
-    for i in 1..trees_hit.len() {
-        prod *= trees_hit[i] as i64;
+    #[test]
+    fn check_map_validation() {
+        let map = example_map();
+        assert_eq!(map.bounds(), (11, 11));
+
+        let no_rows: [&str; 0] = [];
+        assert!(matches!(Map::from_rows(Legend::new(), no_rows.iter()), Err(MapError::EmptyMap)));
+        assert!(matches!(Map::from_rows(Legend::new(), [""].iter()), Err(MapError::EmptyMap)));
+        assert!(matches!(
+            Map::from_rows(Legend::new(), ["..#", ".#", "..."].iter()),
+            Err(MapError::RaggedRow { line: 2, expected: 3, found: 2 })
+        ));
+        assert!(matches!(
+            Map::from_rows(Legend::new(), ["..#", ""].iter()),
+            Err(MapError::RaggedRow { line: 2, expected: 3, found: 0 })
+        ));
+        assert!(matches!(
+            Map::from_rows(Legend::new(), ["..#", ".x."].iter()),
+            Err(MapError::UnknownTerrain { line: 2, column: 2, symbol: 'x' })
+        ));
+    }
+
+    #[test]
+    fn check_sense_location_bounds() {
+        let map = example_map();
+        assert_eq!(map.sense_location((0, 0)).unwrap(), Square::Open);
+        assert_eq!(map.sense_location((2, 0)).unwrap(), Square::Tree);
+        assert_eq!(map.sense_location((10, 10)).unwrap(), Square::Tree);
+
+        for (x, y) in [(11, 0), (0, 11), (11, 11), (-1, 0), (0, -1)] {
+            match map.sense_location((x, y)) {
+                Err(MapError::OutOfBounds { x: found_x, y: found_y }) => assert_eq!((found_x, found_y), (x, y)),
+                other => panic!("{},{} should be out of bounds, got {:?}", x, y, other),
+            }
+        }
+    }
+
+    #[test]
+    fn check_moves_at_the_edges() {
+        let mut map = example_map();
+        assert_eq!(map.move_with_wrap((9, 0), 3, 1).unwrap(), (1, 1));
+        assert!(matches!(map.move_with_wrap((0, 10), 0, 1), Err(MapError::OffMap)));
+
+        map.boundary = Boundary::Stop;
+        assert!(matches!(map.move_with_wrap((9, 0), 3, 1), Err(MapError::OffMap)));
+        map.boundary = Boundary::Clamp;
+        assert_eq!(map.move_with_wrap((9, 0), 3, 1).unwrap(), (10, 1));
+    }
+
+    #[test]
+    fn check_boundary_modes() {
+        // Reflecting on 4 squares repeats 0 1 2 3 2 1 in both directions.
+        let reflected: Vec<Option<i32>> = (-7..=9).map(|position| Boundary::Reflect.resolve(position, 4, false)).collect();
+        let expected = [1, 0, 1, 2, 3, 2, 1, 0, 1, 2, 3, 2, 1, 0, 1, 2, 3];
+        assert_eq!(reflected, expected.iter().map(|column| Some(*column)).collect::<Vec<_>>());
+        assert!((-5..=5).all(|position| Boundary::Reflect.resolve(position, 1, false) == Some(0)));
+
+        assert_eq!(Boundary::Torus.resolve(-1, 4, true), Some(3));
+        assert_eq!(Boundary::Torus.resolve(9, 4, true), Some(1));
+        assert_eq!(Boundary::Clamp.resolve(-9, 4, false), Some(0));
+        assert_eq!(Boundary::Stop.resolve(4, 4, false), None);
+        assert_eq!(Boundary::Stop.resolve(-1, 4, false), None);
+
+        // The torus wraps off the bottom back to the top; the puzzle's map doesn't.
+        let mut map = example_map();
+        assert!(matches!(map.move_with_wrap((0, 10), 0, 1), Err(MapError::OffMap)));
+        map.boundary = Boundary::Torus;
+        assert_eq!(map.move_with_wrap((0, 10), 0, 1).unwrap(), (0, 0));
+        assert_eq!(map.move_with_wrap((2, 9), -3, 4).unwrap(), (10, 2));
+
+        // Steps wider than the map.
+        let landings: Vec<Option<(i32, i32)>> = [Boundary::WrapHorizontal, Boundary::Torus, Boundary::Clamp, Boundary::Reflect, Boundary::Stop]
+            .iter()
+            .map(|boundary| {
+                map.boundary = *boundary;
+                map.move_with_wrap((0, 0), 25, 1).ok()
+            })
+            .collect();
+        assert_eq!(landings, vec![Some((3, 1)), Some((3, 1)), Some((10, 1)), Some((5, 1)), None]);
+        map.boundary = Boundary::WrapHorizontal;
+        assert_eq!(map.move_with_wrap((0, 0), -25, 1).unwrap(), (8, 1));
+        assert_eq!(map.trees_on_slope(14, 1), map.trees_on_slope(3, 1));
+        map.boundary = Boundary::Reflect;
+        assert_eq!(map.trees_on_slope(25, 1), 4);
     }
 
-    println!("The product is: {}", prod);
+    #[test]
+    fn check_example_slopes() {
+        let map = example_map();
+        let slopes = [(1, 1), (3, 1), (5, 1), (7, 1), (1, 2)];
+        let trees: Vec<usize> = slopes.iter().map(|slope| map.trees_on_slope(slope.0, slope.1)).collect();
+        assert_eq!(trees, vec![2, 7, 3, 4, 2]);
+
+        for (slope, expected) in slopes.iter().zip(&trees) {
+            let mut policy = TreeCountingMovePolicy::new(slope.0, slope.1);
+            ride(&mut policy, &map).unwrap();
+            assert_eq!(policy.trees_hit() as usize, *expected);
+        }
+    }
+
+    #[test]
+    fn check_search_slopes() {
+        let map = example_map();
+        let counts = search_slopes(&map, (1, 7), (1, 2));
+        assert_eq!(counts.len(), 14);
+        for slope in [(1, 1), (3, 1), (5, 1), (7, 1), (1, 2)] {
+            let count = counts.iter().find(|count| count.slope == slope).unwrap();
+            assert_eq!(count.trees, map.trees_on_slope(slope.0, slope.1));
+        }
+        let fewest = counts.iter().min_by_key(|count| count.trees).unwrap();
+        assert_eq!((fewest.slope, fewest.trees), ((5, 2), 0));
+        let most = counts.iter().max_by_key(|count| count.trees).unwrap();
+        assert_eq!((most.slope, most.trees), ((3, 1), 7));
+
+        // Down starts at one row whatever the range says.
+        let counts = search_slopes(&map, (3, 3), (-4, 1));
+        assert_eq!(counts.iter().map(|count| count.slope).collect::<Vec<Step>>(), vec![(3, 1)]);
+        assert!(search_slopes(&map, (3, 3), (0, 0)).is_empty());
+
+        let args = |text: &str| text.split_whitespace().map(String::from).collect::<Vec<String>>();
+        assert_eq!(run_search(&map, &args("--across 1..7 --down 1..2 --product 3,1 1,2")), Ok(()));
+        assert_eq!(run_search(&map, &args("--down 0..0")), Err(String::from("The search ranges hold no slopes")));
+        assert_eq!(run_search(&map, &args("--across")), Err(String::from("--across needs a value")));
+        assert_eq!(run_search(&map, &args("--across 3..1")), Err(String::from("Range 3..1 has min above max")));
+        assert_eq!(run_search(&map, &args("--sideways 1..2")), Err(String::from("Unknown search option '--sideways'")));
+        assert_eq!(run_search(&map, &args("--product 3,0")), Err(String::from("'3,0' must move down at least one row")));
+    }
+
+    #[test]
+    fn check_parse_range() {
+        assert_eq!(parse_range("1..7"), Ok((1, 7)));
+        assert_eq!(parse_range("-3..-3"), Ok((-3, -3)));
+        assert_eq!(parse_range("7..1"), Err(String::from("Range 7..1 has min above max")));
+        for text in ["1-7", "1..", "..7", "a..7", "1..7..9", ""] {
+            assert_eq!(parse_range(text), Err(format!("'{}' is not a range like 1..7", text)));
+        }
+    }
+
+    #[test]
+    fn check_parse_step() {
+        assert_eq!(parse_step("3,1"), Ok((3, 1)));
+        assert_eq!(parse_step(" -2, 3 "), Ok((-2, 3)));
+        assert_eq!(parse_step("3,0"), Err(String::from("'3,0' must move down at least one row")));
+        assert_eq!(parse_step("3,-1"), Err(String::from("'3,-1' must move down at least one row")));
+        for text in ["3", "3,1,2", "a,1", ""] {
+            assert!(parse_step(text).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn check_greedy_and_random_policies() {
+        let map = example_map();
+        let moves = vec![(1, 1), (3, 1), (-1, 1)];
+        let mut greedy = GreedyMovePolicy::new(moves.clone());
+        let route = ride(&mut greedy, &map).unwrap().route;
+        assert_eq!(route.len(), 11);
+        assert_eq!(route[1], (1, 1));
+
+        // Each landing is open unless every allowed move from there hit a tree.
+        let mut trees = 0;
+        for pair in route.windows(2) {
+            if map.sense_location(pair[1]).unwrap() == Square::Tree {
+                trees += 1;
+                assert!(moves.iter().all(|step| {
+                    map.sense_location(map.move_with_wrap(pair[0], step.0, step.1).unwrap()).unwrap() == Square::Tree
+                }));
+            }
+        }
+        assert_eq!(greedy.trees_hit(), trees);
+
+        let walk = |seed: u64| {
+            let mut policy = RandomWalkMovePolicy::new(moves.clone(), seed);
+            let route = ride(&mut policy, &map).unwrap().route;
+            (route, policy.trees_hit())
+        };
+        let (route, trees) = walk(7);
+        assert_eq!((route.clone(), trees), walk(7));
+        assert_eq!(route.len(), 11);
+        assert!(route.windows(2).all(|pair| moves.iter().any(|step| map.move_with_wrap(pair[0], step.0, step.1).unwrap() == pair[1])));
+        assert_eq!(trees as usize, map.tally(&route).hits[0]);
+        assert!((0..20).any(|seed| walk(seed).0 != route));
+
+        // A move far past the bottom is skipped rather than overflowing the row.
+        let mut greedy = GreedyMovePolicy::new(vec![(1, i32::MAX), (3, 1)]);
+        let route = ride(&mut greedy, &map).unwrap().route;
+        assert_eq!(route, (0..11).map(|row| (3 * row % 11, row)).collect::<Route>());
+    }
+
+    #[test]
+    fn check_scripted_policy() {
+        let map = example_map();
+        let mut script = ScriptedMovePolicy::new(vec![(3, 1), (3, 1), (-2, 2)]);
+        assert_eq!(ride(&mut script, &map).unwrap().route, vec![(0, 0), (3, 1), (6, 2), (4, 4)]);
+        assert_eq!(script.trees_hit(), 1);
+        assert_eq!(script.describe(), "script of 3 moves (3 taken)");
+        assert!(matches!(script.next_move(&map, (4, 4)), Err(MapError::Stuck(_))));
+
+        // A script that would slide off the bottom stops before the move.
+        let mut script = ScriptedMovePolicy::new(vec![(0, 6), (0, 6)]);
+        assert_eq!(ride(&mut script, &map).unwrap().route, vec![(0, 0), (0, 6)]);
+        let mut script = ScriptedMovePolicy::new(vec![(3, 1), (0, i32::MAX)]);
+        assert_eq!(ride(&mut script, &map).unwrap().route, vec![(0, 0), (3, 1)]);
+        assert_eq!(map.trees_on_slope(3, i32::MAX), 0);
+
+        let path = std::env::temp_dir().join(format!("day3-script-{}.txt", std::process::id()));
+        std::fs::write(&path, "# two moves\n3,1\n\n 1, 2\n").unwrap();
+        let script = ScriptedMovePolicy::read(path.to_str().unwrap()).unwrap();
+        assert_eq!(script.moves, vec![(3, 1), (1, 2)]);
+        std::fs::write(&path, "3,1\n3,0\n").unwrap();
+        let error = ScriptedMovePolicy::read(path.to_str().unwrap()).err().unwrap();
+        assert!(error.ends_with("line 2: '3,0' must move down at least one row"), "{}", error);
+        std::fs::remove_file(&path).unwrap();
+        assert!(ScriptedMovePolicy::read(path.to_str().unwrap()).is_err());
+    }
+
+    #[test]
+    fn check_legend() {
+        let path = std::env::temp_dir().join(format!("day3-legend-{}.txt", std::process::id()));
+        let read = |text: &str| {
+            std::fs::write(&path, text).unwrap();
+            Legend::read(path.to_str().unwrap())
+        };
+        let error = |text: &str| read(text).err().unwrap().split_once(' ').unwrap().1.to_string();
+
+        assert_eq!(error("# tree 1 tree\n\n# rock 5 tree\n"), "line 3: '#' is already in the legend");
+        assert_eq!(error("## tree 1 tree\n"), "line 1: the symbol must be a single character");
+        assert_eq!(error(". snow\n"), "line 1: the cost must be a whole number");
+        assert_eq!(error(". snow -1\n"), "line 1: the cost must be a whole number");
+        assert_eq!(error(". snow lots\n"), "line 1: the cost must be a whole number");
+        assert_eq!(error(".\n"), "line 1: expected 'symbol name cost [tree]'");
+        assert_eq!(error("~ ice 1 slippery\n"), "line 1: only 'tree' may follow the cost");
+        assert_eq!(error("# tree 1 tree tall\n"), "line 1: only 'tree' may follow the cost");
+        assert_eq!(error("\n  \n"), "has no terrains");
+
+        let legend = read("# tree 10 tree\n^ rock 5 tree\n~ ice 1\n. snow 0\n").unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let rows = ["..^~", "^~..", "~.^.", "#..~"];
+        let map = Map::from_rows(legend, rows.iter()).unwrap();
+        assert_eq!(map.sense_location((2, 2)).unwrap(), Square::Tree);
+        assert_eq!(map.sense_location((1, 1)).unwrap(), Square::Open);
+
+        let mut policy = TreeCountingMovePolicy::new(1, 1);
+        let route = ride(&mut policy, &map).unwrap().route;
+        assert_eq!(route, vec![(0, 0), (1, 1), (2, 2), (3, 3)]);
+        assert_eq!(policy.trees_hit(), 1);
+        let tally = map.tally(&route);
+        assert_eq!((tally.hits.clone(), tally.cost), (vec![0, 1, 2, 0], 7));
+        assert_eq!(map.describe_tally(&tally), "cost 7: tree 0, rock 1, ice 2, snow 0");
+    }
+
+    /// The fewest trees over every route from `start` by brute force.
+    fn fewest_trees(map: &Map, start: (i32, i32), max_shift: i32) -> usize {
+        if start.1 as usize + 1 == map.bounds().1 {
+            return 0;
+        }
+        (-max_shift..=max_shift)
+            .filter_map(|shift| map.move_with_wrap(start, shift, 1).ok())
+            .map(|next| fewest_trees(map, next, max_shift) + (map.sense_location(next).unwrap() == Square::Tree) as usize)
+            .min()
+            .unwrap()
+    }
+
+    #[test]
+    fn check_least_trees_path() {
+        let mut map = example_map();
+        let (route, shifts, trees) = map.least_trees_path((0, 0), 0).unwrap();
+        assert_eq!(route, (0..11).map(|row| (0, row)).collect::<Route>());
+        assert_eq!(shifts, vec![0; 10]);
+        assert_eq!(trees, 3);
+
+        let (route, shifts, trees) = map.least_trees_path((0, 0), 1).unwrap();
+        assert_eq!(trees, fewest_trees(&map, (0, 0), 1));
+        assert_eq!(trees, 0);
+        assert_eq!(route.len(), 11);
+        assert_eq!(map.tally(&route).hits[0], trees);
+        assert!(shifts.iter().all(|shift| shift.abs() <= 1));
+        assert!(route.windows(2).zip(&shifts).all(|(pair, shift)| map.move_with_wrap(pair[0], *shift, 1).unwrap() == pair[1]));
+
+        let mut policy = LeastTreesMovePolicy::new(1);
+        let finished = ride(&mut policy, &map).unwrap();
+        assert_eq!(finished.route, route);
+        let columns: Vec<i64> = (0..=shifts.len()).map(|idx| shifts[..idx].iter().map(|shift| *shift as i64).sum()).collect();
+        assert_eq!(finished.columns, columns);
+        assert_eq!(policy.trees_hit(), 0);
+
+        assert_eq!(map.least_trees_path((3, 10), 1).unwrap(), (vec![(3, 10)], vec![], 0));
+        assert!(matches!(map.least_trees_path((11, 0), 1), Err(MapError::OutOfBounds { .. })));
+        assert!(matches!(map.least_trees_path((0, 0), -1), Err(MapError::Stuck(_))));
+
+        // At the right edge a stop boundary can't wrap round to column 0.
+        map.boundary = Boundary::Stop;
+        let (route, _, trees) = map.least_trees_path((10, 0), 1).unwrap();
+        assert_eq!(trees, fewest_trees(&map, (10, 0), 1));
+        assert!(route.windows(2).all(|pair| (pair[0].0 - pair[1].0).abs() <= 1));
+        let (route, _, trees) = map.least_trees_path((10, 0), 0).unwrap();
+        assert_eq!(route, (0..11).map(|row| (10, row)).collect::<Route>());
+        assert_eq!(trees, 5);
+    }
 }
@@ -0,0 +1,115 @@
+use std::io::{self, Write};
+
+use crate::{Map, Ride, Square};
+
+type Colour = [u8; 3];
+
+const OPEN: Colour = [255, 255, 255];
+const TREE: Colour = [34, 120, 50];
+const TREE_HIT: Colour = [220, 30, 40];
+/// Visited squares, one colour per ride, reused if there are more rides.
+const RIDE_COLOURS: [Colour; 6] = [
+    [40, 90, 220],
+    [245, 150, 20],
+    [140, 60, 180],
+    [20, 170, 170],
+    [150, 100, 50],
+    [230, 60, 170],
+];
+
+/// Writes the map as a binary PPM image with each ride drawn over it.
+/// Every square is `scale` pixels across, and the map is repeated left and
+/// right as far as the rides' columns go.  Trees a ride lands on are drawn
+/// in red, other squares it lands on in the ride's colour.
+pub fn write_ppm<W: Write>(out: &mut W, map: &Map, rides: &[Ride], scale: usize) -> io::Result<()> {
+    let (width, height) = map.bounds();
+    let scale = scale.max(1);
+    let unwrapped: Vec<Vec<(i64, usize)>> = rides.iter()
+        .map(|ride| ride.columns.iter().zip(&ride.route).map(|(x, location)| (*x, location.1 as usize)).collect())
+        .collect();
+
+    let columns = unwrapped.iter().flatten().map(|(x, _)| *x);
+    let first_tile = columns.clone().map(|x| x.div_euclid(width as i64)).min().unwrap_or(0);
+    let last_tile = columns.map(|x| x.div_euclid(width as i64)).max().unwrap_or(0);
+    let image_columns = ((last_tile - first_tile + 1) as usize) * width;
+
+    // The colour of every square, starting from the map and painting routes on top.
+    let mut squares: Vec<Vec<Colour>> = (0..height)
+        .map(|row| (0..image_columns)
+            .map(|column| match map.data[row][column % width] {
+                Square::Tree => TREE,
+                _ => OPEN,
+            })
+            .collect())
+        .collect();
+    for (idx, route) in unwrapped.iter().enumerate() {
+        for (x, row) in route {
+            let column = (x - first_tile * width as i64) as usize;
+            squares[*row][column] = match map.data[*row][column % width] {
+                Square::Tree => TREE_HIT,
+                _ => RIDE_COLOURS[idx % RIDE_COLOURS.len()],
+            };
+        }
+    }
+
+    write!(out, "P6\n{} {}\n255\n", image_columns * scale, height * scale)?;
+    let mut line: Vec<u8> = Vec::with_capacity(image_columns * scale * 3);
+    for row in squares {
+        line.clear();
+        for colour in row {
+            for _ in 0..scale {
+                line.extend_from_slice(&colour);
+            }
+        }
+        for _ in 0..scale {
+            out.write_all(&line)?;
+        }
+    }
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{example_map, EXAMPLE};
+    use crate::{ride, Boundary, TreeCountingMovePolicy};
+
+    /// Renders a single slope at one pixel per square, returning the image
+    /// width and the pixels.
+    fn render(map: &Map, slope: (i32, i32)) -> (usize, Vec<Colour>) {
+        let mut policy = TreeCountingMovePolicy::new(slope.0, slope.1);
+        let rides = [ride(&mut policy, map).unwrap()];
+        let mut image: Vec<u8> = vec![];
+        write_ppm(&mut image, map, &rides, 1).unwrap();
+
+        // The header is three lines: the magic number, the size and the maximum value.
+        let header_end = image.iter().enumerate().filter(|(_, byte)| **byte == b'\n').nth(2).unwrap().0 + 1;
+        let header = std::str::from_utf8(&image[..header_end]).unwrap();
+        let fields: Vec<&str> = header.split_whitespace().collect();
+        assert_eq!((fields[0], fields[2], fields[3]), ("P6", "11", "255"));
+        let pixels = image[header_end..].chunks(3).map(|pixel| [pixel[0], pixel[1], pixel[2]]).collect();
+        (fields[1].parse().unwrap(), pixels)
+    }
+
+    #[test]
+    fn check_wide_steps_are_drawn_in_full() {
+        let mut map = example_map();
+        let (width, pixels) = render(&map, (7, 1));
+        assert_eq!(width, 77);
+        assert_eq!(pixels.len(), 77 * 11);
+
+        let expected: Vec<usize> = (0..11).map(|row| row * 77 + row * 7).collect();
+        let painted: Vec<usize> = (0..pixels.len()).filter(|idx| pixels[*idx] != OPEN && pixels[*idx] != TREE).collect();
+        assert_eq!(painted, expected);
+        for (row, idx) in expected.iter().enumerate() {
+            let hit = EXAMPLE[row].as_bytes()[row * 7 % 11] == b'#';
+            assert_eq!(pixels[*idx], if hit { TREE_HIT } else { RIDE_COLOURS[0] }, "row {}", row);
+        }
+
+        // Without wrapping the ride stays on one copy of the map.
+        map.boundary = Boundary::Clamp;
+        let (width, pixels) = render(&map, (7, 1));
+        assert_eq!(width, 11);
+        assert!([0, 11 + 7, 2 * 11 + 10, 10 * 11 + 10].iter().all(|idx| pixels[*idx] != OPEN && pixels[*idx] != TREE));
+    }
+}
@@ -0,0 +1,428 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+
+use crate::schema::Schema;
+use crate::Passport;
+
+/// A line of a JSON lines or CSV file that could not be read.
+#[derive(Debug)]
+pub struct FormatError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl FormatError {
+    fn new(line: usize, message: &str) -> Self {
+        FormatError { line, message: message.to_string() }
+    }
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Line {}: {}", self.line, self.message)
+    }
+}
+
+/// A passport's fields in the order read, then its unknown fields.
+fn all_fields(passport: &Passport) -> impl Iterator<Item = (&String, &String)> {
+    passport.fields.iter().map(|(key, value)| (key, value)).chain(passport.extras.iter())
+}
+
+/// Writes each record as its `key:value` tokens on one line, with a blank
+/// line between records.  The format has no quoting, so a record that would
+/// not read back the same is an `InvalidData` error: one with no fields, a
+/// key holding `:` or whitespace, or a value holding whitespace.
+pub fn write_batch<W: Write>(out: &mut W, batch: &[Passport]) -> io::Result<()> {
+    // Every record is checked before anything is written.
+    let mut lines: Vec<String> = Vec::with_capacity(batch.len());
+    for (idx, passport) in batch.iter().enumerate() {
+        let unwritable = |message: String| io::Error::new(io::ErrorKind::InvalidData, format!("Record {}: {}", idx + 1, message));
+        if passport.fields.is_empty() && passport.extras.is_empty() {
+            return Err(unwritable(String::from("a record with no fields can't be written as a batch")));
+        }
+
+        let mut tokens: Vec<String> = vec![];
+        for (key, value) in all_fields(passport) {
+            if key.is_empty() || key.contains(|c: char| c == ':' || c.is_whitespace()) {
+                return Err(unwritable(format!("the field name '{}' can't be written as a batch", key)));
+            }
+            if value.contains(char::is_whitespace) {
+                return Err(unwritable(format!("{} holds whitespace, which a batch can't hold", key)));
+            }
+            tokens.push(format!("{}:{}", key, value));
+        }
+        lines.push(tokens.join(" "));
+    }
+
+    if lines.is_empty() {
+        return Ok(());
+    }
+    writeln!(out, "{}", lines.join("\n\n"))
+}
+
+pub fn json_string(text: &str) -> String {
+    let mut result = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            c if (c as u32) < 0x20 => result.push_str(&format!("\\u{:04x}", c as u32)),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+/// Writes one JSON object of string values per record.
+pub fn write_json_lines<W: Write>(out: &mut W, batch: &[Passport]) -> io::Result<()> {
+    for passport in batch {
+        let members: Vec<String> = all_fields(passport)
+            .map(|(key, value)| format!("{}:{}", json_string(key), json_string(value)))
+            .collect();
+        writeln!(out, "{{{}}}", members.join(","))?;
+    }
+    Ok(())
+}
+
+/// Reads one flat JSON object of string values per line, such as
+/// `write_json_lines` produces.  Blank lines are skipped.
+pub fn read_json_lines<R: BufRead>(reader: R, schema: &Schema) -> Result<Vec<Passport>, FormatError> {
+    let mut result: Vec<Passport> = vec![];
+    for (idx, line) in reader.lines().enumerate() {
+        let line = line.map_err(|error| FormatError::new(idx + 1, &error.to_string()))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let mut passport = Passport::new();
+        passport.lines = (idx + 1, idx + 1);
+        let members = parse_json_object(&line).map_err(|message| FormatError::new(idx + 1, &message))?;
+        for (key, value) in members {
+            passport.set_value(schema, &key, &value);
+        }
+        result.push(passport);
+    }
+    Ok(result)
+}
+
+type Chars<'a> = std::iter::Peekable<std::str::Chars<'a>>;
+
+/// The next character that is not whitespace, consumed.
+fn next_token(chars: &mut Chars) -> Option<char> {
+    while chars.next_if(|c| c.is_whitespace()).is_some() {}
+    chars.next()
+}
+
+fn expect(chars: &mut Chars, wanted: char) -> Result<(), String> {
+    match next_token(chars) {
+        Some(c) if c == wanted => Ok(()),
+        Some(c) => Err(format!("Expected '{}' but found '{}'", wanted, c)),
+        None => Err(format!("Expected '{}' but the line ended", wanted)),
+    }
+}
+
+/// Parses `{"key":"value",...}` with every value a string.
+fn parse_json_object(text: &str) -> Result<Vec<(String, String)>, String> {
+    let mut chars = text.chars().peekable();
+    let mut members: Vec<(String, String)> = vec![];
+    expect(&mut chars, '{')?;
+    while chars.next_if(|c| c.is_whitespace()).is_some() {}
+    if chars.next_if_eq(&'}').is_none() {
+        loop {
+            expect(&mut chars, '"')?;
+            let key = parse_json_string(&mut chars)?;
+            expect(&mut chars, ':')?;
+            expect(&mut chars, '"').map_err(|_| format!("The value of '{}' must be a string", key))?;
+            let value = parse_json_string(&mut chars)?;
+            members.push((key, value));
+
+            match next_token(&mut chars) {
+                Some(',') => continue,
+                Some('}') => break,
+                Some(c) => return Err(format!("Expected ',' or '}}' but found '{}'", c)),
+                None => return Err(String::from("The object is not closed")),
+            }
+        }
+    }
+
+    if next_token(&mut chars).is_some() {
+        return Err(String::from("Unexpected text after the object"));
+    }
+    Ok(members)
+}
+
+/// Reads the rest of a string whose opening quote has been consumed.
+fn parse_json_string(chars: &mut Chars) -> Result<String, String> {
+    let mut result = String::new();
+    loop {
+        match chars.next() {
+            Some('"') => return Ok(result),
+            Some('\\') => {
+                let escaped = match chars.next() {
+                    Some('"') => '"',
+                    Some('\\') => '\\',
+                    Some('/') => '/',
+                    Some('b') => '\u{8}',
+                    Some('f') => '\u{c}',
+                    Some('n') => '\n',
+                    Some('r') => '\r',
+                    Some('t') => '\t',
+                    Some('u') => {
+                        let mut code = parse_hex4(chars)?;
+                        if (0xD800..0xDC00).contains(&code) {
+                            // A surrogate pair spells one character in two escapes.
+                            if chars.next() != Some('\\') || chars.next() != Some('u') {
+                                return Err(String::from("Unpaired surrogate in string"));
+                            }
+                            let low = parse_hex4(chars)?;
+                            if !(0xDC00..0xE000).contains(&low) {
+                                return Err(String::from("Unpaired surrogate in string"));
+                            }
+                            code = 0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00);
+                        }
+                        char::from_u32(code).ok_or("Invalid \\u escape in string")?
+                    },
+                    _ => return Err(String::from("Invalid escape in string")),
+                };
+                result.push(escaped);
+            },
+            Some(c) => result.push(c),
+            None => return Err(String::from("Unterminated string")),
+        }
+    }
+}
+
+fn parse_hex4(chars: &mut Chars) -> Result<u32, String> {
+    let digits: String = chars.take(4).collect();
+    if digits.len() != 4 {
+        return Err(String::from("Short \\u escape in string"));
+    }
+    u32::from_str_radix(&digits, 16).map_err(|_| format!("'{}' is not a hex escape", digits))
+}
+
+pub fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Writes a column for each schema field and then for each unknown field
+/// in the batch.  An empty cell stands for a field that was not given and a
+/// quoted `""` for one given with an empty value.  `read_csv` takes one row
+/// per line, so a field name or value holding a line break is an
+/// `InvalidData` error and nothing is written.
+pub fn write_csv<W: Write>(out: &mut W, batch: &[Passport], schema: &Schema) -> io::Result<()> {
+    let extras: BTreeSet<&String> = batch.iter().flat_map(|passport| passport.extras.keys()).collect();
+    let columns: Vec<&String> = schema.fields.iter().map(|field| &field.name).chain(extras).collect();
+    let line_break = |text: &str| text.contains(['\n', '\r']);
+
+    if let Some(name) = columns.iter().find(|name| line_break(name)) {
+        let message = format!("the field name {} holds a line break, which a CSV row can't hold", json_string(name));
+        return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+    }
+    let mut rows: Vec<String> = Vec::with_capacity(batch.len());
+    for (idx, passport) in batch.iter().enumerate() {
+        let mut row: Vec<String> = vec![];
+        for name in &columns {
+            let value = passport.extras.get(*name)
+                .or_else(|| passport.fields.iter().find(|(key, _)| key == *name).map(|(_, value)| value));
+            row.push(match value {
+                Some(value) if line_break(value) => {
+                    let message = format!("Record {}: {} holds a line break, which a CSV row can't hold", idx + 1, name);
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+                },
+                Some(value) if value.is_empty() => String::from("\"\""),
+                Some(value) => csv_field(value),
+                None => String::new(),
+            });
+        }
+        rows.push(row.join(","));
+    }
+
+    let header: Vec<String> = columns.iter().map(|name| csv_field(name)).collect();
+    writeln!(out, "{}", header.join(","))?;
+    for row in rows {
+        writeln!(out, "{}", row)?;
+    }
+    Ok(())
+}
+
+/// Reads a CSV file with a header row naming the fields.  Quoted cells may
+/// hold commas and doubled quotes but not line breaks.  An empty cell is a
+/// field that wasn't given; a quoted empty cell is one given empty.
+pub fn read_csv<R: BufRead>(reader: R, schema: &Schema) -> Result<Vec<Passport>, FormatError> {
+    let mut lines = reader.lines();
+    let header = match lines.next() {
+        Some(line) => split_csv_row(&line.map_err(|error| FormatError::new(1, &error.to_string()))?)
+            .map_err(|message| FormatError::new(1, &message))?,
+        None => return Ok(vec![]),
+    };
+
+    let mut result: Vec<Passport> = vec![];
+    for (idx, line) in lines.enumerate() {
+        let line_number = idx + 2;
+        let line = line.map_err(|error| FormatError::new(line_number, &error.to_string()))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let cells = split_csv_cells(&line).map_err(|message| FormatError::new(line_number, &message))?;
+        if cells.len() != header.len() {
+            return Err(FormatError::new(line_number, &format!("Expected {} cells, found {}", header.len(), cells.len())));
+        }
+
+        let mut passport = Passport::new();
+        passport.lines = (line_number, line_number);
+        for (name, (value, quoted)) in header.iter().zip(&cells) {
+            if *quoted || !value.is_empty() {
+                passport.set_value(schema, name, value);
+            }
+        }
+        result.push(passport);
+    }
+    Ok(result)
+}
+
+pub fn split_csv_row(line: &str) -> Result<Vec<String>, String> {
+    Ok(split_csv_cells(line)?.into_iter().map(|(cell, _)| cell).collect())
+}
+
+/// Splits a row into its cells, each with whether it was quoted.
+fn split_csv_cells(line: &str) -> Result<Vec<(String, bool)>, String> {
+    let mut cells: Vec<(String, bool)> = vec![];
+    let mut chars = line.chars().peekable();
+    loop {
+        let mut cell = String::new();
+        let quoted = chars.peek() == Some(&'"');
+        if quoted {
+            chars.next();
+            loop {
+                match chars.next() {
+                    Some('"') if chars.peek() == Some(&'"') => {
+                        chars.next();
+                        cell.push('"');
+                    },
+                    Some('"') => break,
+                    Some(c) => cell.push(c),
+                    None => return Err(String::from("Unterminated quoted cell")),
+                }
+            }
+        }
+        while let Some(c) = chars.next_if(|c| *c != ',') {
+            cell.push(c);
+        }
+        cells.push((cell, quoted));
+        if chars.next().is_none() {
+            return Ok(cells);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Pairs = Vec<(String, String)>;
+
+    /// What a round trip has to keep: the fields in order and the extras.
+    fn contents(batch: &[Passport]) -> Vec<(Pairs, Pairs)> {
+        batch.iter()
+            .map(|passport| (
+                passport.fields.clone(),
+                passport.extras.iter().map(|(key, value)| (key.clone(), value.clone())).collect(),
+            ))
+            .collect()
+    }
+
+    const BATCH: &str = "ecl:gry pid:860033327 eyr:2020 hcl:#fffffd\nbyr:1937 iyr:2017 cid:147 hgt:183cm\n\n\
+        iyr:2013 ecl:amb cid:350 eyr:2023 pid:028048884 pet:\"rudolph,jr\"\nhcl:#cfa07d byr:1929 sleigh:é\\ú\n\n\
+        hcl:#ae17e1 cid: notes:a,b,\"c\"\n";
+
+    #[test]
+    fn check_batch_and_json_round_trip() {
+        let schema = Schema::profile("north-pole", 2020).unwrap();
+        let original = Passport::records(BATCH.as_bytes(), &schema).collect::<io::Result<Vec<_>>>().unwrap();
+        assert_eq!(original.len(), 3);
+        assert_eq!(original[1].extras.get("pet").map(|pet| pet.as_str()), Some("\"rudolph,jr\""));
+
+        let mut batch_text: Vec<u8> = vec![];
+        write_batch(&mut batch_text, &original).unwrap();
+        let from_batch = Passport::records(batch_text.as_slice(), &schema).collect::<io::Result<Vec<_>>>().unwrap();
+        assert_eq!(contents(&from_batch), contents(&original));
+
+        let mut json: Vec<u8> = vec![];
+        write_json_lines(&mut json, &original).unwrap();
+        let from_json = read_json_lines(json.as_slice(), &schema).unwrap();
+        assert_eq!(contents(&from_json), contents(&original));
+        assert_eq!(from_json[2].lines, (3, 3));
+
+        let mut again: Vec<u8> = vec![];
+        write_json_lines(&mut again, &from_json).unwrap();
+        assert_eq!(again, json);
+
+        // Whitespace in a value, or a record with nothing in it, can't be written as a batch.
+        let spaced = read_json_lines("{\"ecl\":\"blu\"}\n{\"name\":\"Santa Claus\"}\n".as_bytes(), &schema).unwrap();
+        let error = write_batch(&mut Vec::new(), &spaced).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(error.to_string(), "Record 2: name holds whitespace, which a batch can't hold");
+        assert!(write_batch(&mut Vec::new(), &read_json_lines("{}".as_bytes(), &schema).unwrap()).is_err());
+        assert!(write_batch(&mut Vec::new(), &read_json_lines("{\"a:b\":\"c\"}".as_bytes(), &schema).unwrap()).is_err());
+
+        let escaped = read_json_lines("{\"a\\u00e9\":\"x\\ud83c\\udf84\\n\", \"ecl\" : \"blu\"}\n{}\n".as_bytes(), &schema).unwrap();
+        assert_eq!(escaped[0].extras.get("aé").map(|value| value.as_str()), Some("x\u{1F384}\n"));
+        assert_eq!(escaped[0].get("ecl"), "blu");
+        assert!(escaped[1].fields.is_empty());
+        for bad in ["{\"ecl\":3}", "{\"a\":\"\\ud83c\\u0041\"}", "{\"ecl\":\"blu\"", "[]", "{\"ecl\":\"blu\"} x"] {
+            assert!(read_json_lines(bad.as_bytes(), &schema).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn check_csv_round_trip() {
+        let schema = Schema::profile("north-pole", 2020).unwrap();
+        let original = Passport::records(BATCH.as_bytes(), &schema).collect::<io::Result<Vec<_>>>().unwrap();
+        assert_eq!(original[2].fields[1], (String::from("cid"), String::new()));
+
+        let mut csv: Vec<u8> = vec![];
+        write_csv(&mut csv, &original, &schema).unwrap();
+        let text = String::from_utf8(csv.clone()).unwrap();
+        assert!(text.starts_with("byr,iyr,eyr,hgt,hcl,ecl,pid,cid,notes,pet,sleigh\n"));
+        assert!(text.contains(",\"a,b,\"\"c\"\"\","));
+        // The third record gives cid empty and leaves byr out.
+        assert!(text.ends_with("\n,,,,#ae17e1,,,\"\",\"a,b,\"\"c\"\"\",,\n"), "{}", text);
+
+        let from_csv = read_csv(csv.as_slice(), &schema).unwrap();
+        let sorted = |batch: &[Passport]| {
+            let mut result = contents(batch);
+            result.iter_mut().for_each(|(fields, _)| fields.sort());
+            result
+        };
+        assert_eq!(sorted(&from_csv), sorted(&original));
+
+        let mut again: Vec<u8> = vec![];
+        write_csv(&mut again, &from_csv, &schema).unwrap();
+        assert_eq!(again, csv);
+
+        // Rows are single lines, so line breaks can't be written.
+        let broken = read_json_lines("{\"ecl\":\"blu\"}\n{\"notes\":\"two\\nlines\"}\n".as_bytes(), &schema).unwrap();
+        let mut out: Vec<u8> = vec![];
+        let error = write_csv(&mut out, &broken, &schema).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(error.to_string(), "Record 2: notes holds a line break, which a CSV row can't hold");
+        assert!(out.is_empty());
+        let broken = read_json_lines("{\"a\\rb\":\"c\"}\n".as_bytes(), &schema).unwrap();
+        assert_eq!(write_csv(&mut Vec::new(), &broken, &schema).unwrap_err().to_string(),
+            "the field name \"a\\rb\" holds a line break, which a CSV row can't hold");
+        assert!(read_csv("\"notes\"\n\"two\nlines\"\n".as_bytes(), &schema).is_err());
+
+        assert!(read_csv("byr,iyr\n1937\n".as_bytes(), &schema).is_err());
+        assert!(read_csv("byr\n\"1937\n".as_bytes(), &schema).is_err());
+    }
+}
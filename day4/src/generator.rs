@@ -0,0 +1,359 @@
+use std::io::{self, BufRead, Write};
+
+use crate::formats::{csv_field, split_csv_row, FormatError};
+use crate::schema::{Field, Rule, Schema};
+use crate::Passport;
+
+/// The random source behind every generated value, seeded so that a
+/// manifest can be regenerated exactly from its seed.  SplitMix64.
+struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Rng { state: seed }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `0..bound`.
+    fn below(&mut self, bound: usize) -> usize {
+        (self.next_u64() % bound as u64) as usize
+    }
+
+    /// A number in the inclusive range.
+    fn between(&mut self, min: i64, max: i64) -> i64 {
+        min + (self.next_u64() % ((max - min) as u64 + 1)) as i64
+    }
+
+    fn pick(&mut self, from: &[u8], length: usize) -> String {
+        (0..length).map(|_| from[self.below(from.len())] as char).collect()
+    }
+}
+
+const HEX: &[u8] = b"0123456789abcdef";
+const LOWER: &[u8] = b"abcdefghijklmnopqrstuvwxyz";
+const DIGITS: &[u8] = b"0123456789";
+const ALNUM: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+
+/// A synthesized record, what it is testing and whether it should pass.
+pub struct Case {
+    pub description: String,
+    pub valid: bool,
+    pub passport: Passport,
+}
+
+/// Makes batches of records for testing validators against a schema.  The
+/// same seed always produces the same batch.
+pub struct Generator {
+    rng: Rng,
+}
+
+impl Generator {
+    pub fn new(seed: u64) -> Self {
+        Generator { rng: Rng::new(seed) }
+    }
+
+    /// The widest range a number rule can produce with its digit count.
+    fn number_range(digits: usize, min: i64, max: i64) -> Option<(i64, i64)> {
+        let largest = 10i64.checked_pow(digits as u32).map_or(i64::MAX, |limit| limit - 1);
+        let (min, max) = (min.max(0), max.min(largest));
+        if digits == 0 || min > max {
+            None
+        } else {
+            Some((min, max))
+        }
+    }
+
+    /// A random value the rule accepts, or `None` if none was found.
+    /// Patterns are matched by trying candidates of a few common shapes.
+    fn valid_value(&mut self, rule: &Rule) -> Option<String> {
+        match rule {
+            Rule::Any => {
+                let length = 1 + self.rng.below(8);
+                Some(self.rng.pick(ALNUM, length))
+            },
+            Rule::Number { digits, min, max } => {
+                let (min, max) = Generator::number_range(*digits, *min, *max)?;
+                Some(format!("{:0width$}", self.rng.between(min, max), width = digits))
+            },
+            Rule::Units(units) => {
+                let unit = &units[self.rng.below(units.len())];
+                Some(format!("{}{}", self.rng.between(unit.min.max(0), unit.max.max(0)), unit.suffix)).filter(|value| rule.check(value))
+            },
+            Rule::Pattern { .. } => {
+                for attempt in 0..400 {
+                    let length = 1 + self.rng.below(9);
+                    let candidate = match attempt % 4 {
+                        0 => format!("#{}", self.rng.pick(HEX, 6)),
+                        1 => self.rng.pick(LOWER, length),
+                        2 => self.rng.pick(DIGITS, length),
+                        _ => self.rng.pick(ALNUM, length),
+                    };
+                    if rule.check(&candidate) {
+                        return Some(candidate);
+                    }
+                }
+                None
+            },
+            Rule::OneOf(values) => Some(values[self.rng.below(values.len())].clone()),
+        }
+    }
+
+    /// Valid values at the edges of the rule's ranges.
+    fn boundaries(rule: &Rule) -> Vec<(String, String)> {
+        let mut result: Vec<(String, String)> = vec![];
+        match rule {
+            Rule::Number { digits, min, max } => {
+                if let Some((min, max)) = Generator::number_range(*digits, *min, *max) {
+                    result.push((format!("{:0width$}", min, width = digits), String::from("at min")));
+                    result.push((format!("{:0width$}", max, width = digits), String::from("at max")));
+                }
+            },
+            Rule::Units(units) => {
+                for unit in units {
+                    result.push((format!("{}{}", unit.min, unit.suffix), format!("at {} min", unit.suffix)));
+                    result.push((format!("{}{}", unit.max, unit.suffix), format!("at {} max", unit.suffix)));
+                }
+            },
+            _ => {},
+        }
+        result.into_iter().filter(|(value, _)| rule.check(value)).collect()
+    }
+
+    /// Values breaking the rule in each way it can be broken, built around
+    /// a valid value.  Only values the rule really rejects are returned.
+    fn violations(&mut self, rule: &Rule, valid: &str) -> Vec<(String, String)> {
+        let mut result: Vec<(String, String)> = vec![];
+        let mut add = |value: String, description: &str| result.push((value, description.to_string()));
+        let without_first: String = valid.chars().skip(1).collect();
+        let without_last: String = valid.chars().take(valid.chars().count().saturating_sub(1)).collect();
+
+        match rule {
+            Rule::Any => {},
+            Rule::Number { digits, min, max } => {
+                // Bounds at the ends of i64 have nothing beyond them to try.
+                if let Some(below) = min.checked_sub(1) {
+                    add(format!("{:0width$}", below, width = digits), "below min");
+                }
+                if let Some(above) = max.checked_add(1) {
+                    add(format!("{:0width$}", above, width = digits), "above max");
+                }
+                add(without_first.clone(), "one digit short");
+                add(format!("0{}", valid), "one digit long");
+                add(format!("+{}", without_first), "with a sign");
+                add(format!("{}x", without_last), "not a number");
+            },
+            Rule::Units(units) => {
+                for unit in units {
+                    if let Some(below) = unit.min.checked_sub(1) {
+                        add(format!("{}{}", below, unit.suffix), &format!("below {} min", unit.suffix));
+                    }
+                    if let Some(above) = unit.max.checked_add(1) {
+                        add(format!("{}{}", above, unit.suffix), &format!("above {} max", unit.suffix));
+                    }
+                    // A value fine in this unit but written with each other unit.
+                    let number = unit.max;
+                    for other in units.iter().filter(|other| other.suffix != unit.suffix) {
+                        add(format!("{}{}", number, other.suffix), "wrong unit");
+                    }
+                }
+                let number: String = valid.chars().take_while(|c| c.is_ascii_digit()).collect();
+                add(number.clone(), "no unit");
+                add(format!("{}zz", number), "unknown unit");
+                add(format!("+{}", valid), "with a sign");
+            },
+            Rule::Pattern { .. } => {
+                add(valid.to_uppercase(), "uppercase");
+                add(without_last.clone(), "one character short");
+                add(format!("{}{}", valid, valid.chars().last().unwrap_or('x')), "one character long");
+                add(format!("{}z", without_last), "bad character");
+                add(without_first, "missing first character");
+                add(format!("x{}", valid), "leading junk");
+            },
+            Rule::OneOf(values) => {
+                add(valid.to_uppercase(), "uppercase");
+                add(without_last, "truncated");
+                let unlisted = (0..100).map(|_| self.rng.pick(LOWER, 3)).find(|candidate| !values.contains(candidate));
+                if let Some(unlisted) = unlisted {
+                    add(unlisted, "not listed");
+                }
+            },
+        }
+
+        let mut seen: Vec<String> = vec![];
+        result.into_iter()
+            .filter(|(value, _)| !value.is_empty() && !value.contains(char::is_whitespace) && !rule.check(value))
+            .filter(|(value, _)| {
+                let fresh = !seen.contains(value);
+                seen.push(value.clone());
+                fresh
+            })
+            .collect()
+    }
+
+    /// Values for every field, or an error naming a field no valid value
+    /// could be found for.
+    fn valid_values(&mut self, schema: &Schema) -> Result<Vec<String>, String> {
+        schema.fields.iter()
+            .map(|field| self.valid_value(&field.rule).ok_or_else(|| format!("No valid value found for {}", field.name)))
+            .collect()
+    }
+
+    fn record(schema: &Schema, values: &[String], description: String, valid: bool) -> Case {
+        let mut passport = Passport::new();
+        for (field, value) in schema.fields.iter().zip(values) {
+            if !value.is_empty() {
+                passport.set_value(schema, &field.name, value);
+            }
+        }
+        Case { description, valid, passport }
+    }
+
+    /// A shuffled batch of `valid` random valid records, then `each` records
+    /// for every boundary value, every field left out and every way each
+    /// field can break its rule.  Leaving out the only field of a one-field
+    /// schema would leave an empty record, which a batch can't hold, so
+    /// that case is skipped.
+    pub fn batch(&mut self, schema: &Schema, valid: usize, each: usize) -> Result<Vec<Case>, String> {
+        let mut cases: Vec<Case> = vec![];
+        for _ in 0..valid {
+            let values = self.valid_values(schema)?;
+            cases.push(Generator::record(schema, &values, String::from("valid"), true));
+        }
+
+        for _ in 0..each {
+            for (idx, field) in schema.fields.iter().enumerate() {
+                let Field { name, required, rule } = field;
+                let values = self.valid_values(schema)?;
+                let with = |value: &str| {
+                    let mut values = values.clone();
+                    values[idx] = value.to_string();
+                    values
+                };
+
+                for (value, description) in Generator::boundaries(rule) {
+                    cases.push(Generator::record(schema, &with(&value), format!("{} {} {}", name, value, description), true));
+                }
+                let description = if *required { format!("missing {}", name) } else { format!("missing {} (optional)", name) };
+                let missing = Generator::record(schema, &with(""), description, !required);
+                if !missing.passport.fields.is_empty() {
+                    cases.push(missing);
+                }
+                for (value, description) in self.violations(rule, &values[idx]) {
+                    cases.push(Generator::record(schema, &with(&value), format!("{} {} {}", name, value, description), false));
+                }
+            }
+        }
+
+        for idx in (1..cases.len()).rev() {
+            let other = self.rng.below(idx + 1);
+            cases.swap(idx, other);
+        }
+        Ok(cases)
+    }
+}
+
+/// Writes `record,expected,case` for each case, numbering records from 1
+/// in batch order.
+pub fn write_manifest<W: Write>(out: &mut W, cases: &[Case]) -> io::Result<()> {
+    writeln!(out, "record,expected,case")?;
+    for (idx, case) in cases.iter().enumerate() {
+        writeln!(out, "{},{},{}", idx + 1, if case.valid { "valid" } else { "invalid" }, csv_field(&case.description))?;
+    }
+    Ok(())
+}
+
+/// One manifest row: the record number, whether it should pass and why.
+pub struct Expectation {
+    pub record: usize,
+    pub valid: bool,
+    pub description: String,
+}
+
+pub fn read_manifest<R: BufRead>(reader: R) -> Result<Vec<Expectation>, FormatError> {
+    let mut result: Vec<Expectation> = vec![];
+    for (idx, line) in reader.lines().enumerate().skip(1) {
+        let bad = |message: &str| FormatError { line: idx + 1, message: message.to_string() };
+        let line = line.map_err(|error| bad(&error.to_string()))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let cells = split_csv_row(&line).map_err(|message| bad(&message))?;
+        let (record, expected, description) = match cells.as_slice() {
+            [record, expected, description] => (record, expected, description),
+            _ => return Err(bad("Expected record,expected,case")),
+        };
+        let record = record.parse().map_err(|_| bad("The record must be a number"))?;
+        let valid = match expected.as_str() {
+            "valid" => true,
+            "invalid" => false,
+            _ => return Err(bad("Expected must be valid or invalid")),
+        };
+        result.push(Expectation { record, valid, description: description.clone() });
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ParseMode;
+
+    #[test]
+    fn check_synthesized_batches() {
+        for (profile, year) in [("north-pole", 2020), ("passport", 2020), ("travel", 2026)] {
+            let schema = Schema::profile(profile, year).unwrap();
+            let cases = Generator::new(2020).batch(&schema, 20, 2).unwrap();
+            for case in &cases {
+                assert_eq!(case.passport.is_valid(&schema, ParseMode::Strict), case.valid, "{}: {}", profile, case.description);
+            }
+        }
+
+        let schema = Schema::profile("north-pole", 2020).unwrap();
+        let cases = Generator::new(7).batch(&schema, 5, 1).unwrap();
+        let descriptions: Vec<&str> = cases.iter().map(|case| case.description.as_str()).collect();
+        for expected in ["byr 1920 at min", "byr 2002 at max", "byr 1919 below min", "byr 2003 above max",
+            "missing ecl", "missing cid (optional)", "hgt 75cm wrong unit", "hgt 193in wrong unit", "hgt 58in below in min"] {
+            assert!(descriptions.contains(&expected), "{} not in {:?}", expected, descriptions);
+        }
+        assert!(descriptions.iter().any(|description| description.starts_with("hcl #") && description.ends_with("uppercase")));
+        assert!(descriptions.iter().any(|description| description.starts_with("pid +")));
+        assert_eq!(descriptions.iter().filter(|description| **description == "valid").count(), 5);
+
+        let again: Vec<String> = Generator::new(7).batch(&schema, 5, 1).unwrap().into_iter().map(|case| case.description).collect();
+        assert_eq!(again, descriptions);
+
+        let mut manifest: Vec<u8> = vec![];
+        write_manifest(&mut manifest, &cases).unwrap();
+        let read = read_manifest(manifest.as_slice()).unwrap();
+        assert_eq!(read.len(), cases.len());
+        assert!(read.iter().zip(&cases).enumerate().all(|(idx, (row, case))| {
+            row.record == idx + 1 && row.valid == case.valid && row.description == case.description
+        }));
+
+        let extremes = Schema::parse_at("a required number 19 0..9223372036854775807\nb required units x:0..9223372036854775807", 2020).unwrap();
+        let cases = Generator::new(3).batch(&extremes, 5, 1).unwrap();
+        assert!(cases.iter().all(|case| case.passport.is_valid(&extremes, ParseMode::Strict) == case.valid));
+        assert!(cases.iter().any(|case| case.description == "a 9223372036854775807 at max"));
+
+        // Leaving out the only field would give an empty record.
+        let single = Schema::parse_at("a required number 25 0..5", 2020).unwrap();
+        let cases = Generator::new(4).batch(&single, 5, 2).unwrap();
+        assert!(cases.iter().all(|case| case.passport.is_valid(&single, ParseMode::Strict) == case.valid));
+        assert!(cases.iter().all(|case| !case.description.starts_with("missing")));
+        let passports: Vec<Passport> = cases.into_iter().map(|case| case.passport).collect();
+        crate::formats::write_batch(&mut Vec::new(), &passports).unwrap();
+
+        let impossible = Schema::parse_at("a required pattern [0-9]{20}", 2020).unwrap();
+        assert!(Generator::new(1).batch(&impossible, 1, 0).is_err());
+    }
+}
@@ -1,152 +1,519 @@
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt;
 use std::io::{Result, BufReader, BufRead};
 use std::fs::File;
-use regex::Regex;
 
-#[derive(Debug)]
+mod formats;
+mod generator;
+mod report;
+mod schema;
+mod typed;
+
+use schema::Schema;
+use typed::ValidPassport;
+
+/// A document's fields in the order they were read.  Fields the schema does
+/// not know go in `extras`.  A field given twice keeps its first value and
+/// the repeat is recorded as a warning.
+#[derive(Clone, Debug)]
 struct Passport {
-    byr: String,
-    iyr: String,
-    eyr: String,
-    hgt: String,
-    hcl: String,
-    ecl: String,
-    pid: String,
-    cid: String,
+    fields: Vec<(String, String)>,
+    extras: BTreeMap<String, String>,
+    warnings: Vec<Warning>,
+    /// The first and last source lines of the record, or zeros if it had none.
+    lines: (usize, usize),
 }
 
-fn test_range_number(test: &str, len: usize, min: i32, max: i32) -> bool {
-    let number:i32 = test.parse().unwrap_or_default();
-    test.trim().len() == len && number >= min && number <= max
+/// Something odd about how a record was written, noticed while reading it.
+#[derive(Clone, Debug, PartialEq)]
+enum Warning {
+    /// A field given again, with the value that was not kept.
+    Duplicate { field: String, value: String, line: usize },
+    /// A token that is not `key:value`.
+    Malformed { token: String, line: usize },
 }
 
-fn valid_birth_year(test: &str) -> bool {
-    test_range_number(test, 4, 1920, 2002)
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Warning::Duplicate { field, value, line } =>
+                write!(f, "line {}: {} given again as '{}', keeping the first value", line, field, value),
+            Warning::Malformed { token, line } => write!(f, "line {}: '{}' is not key:value", line, token),
+        }
+    }
 }
 
-fn valid_issue_year(test: &str) -> bool {
-    test_range_number(test, 4, 2010, 2020)
+/// How seriously to take unknown fields and warnings.  Lenient mode keeps
+/// them for reporting; strict mode makes them invalidate the record.
+#[derive(Clone, Copy, PartialEq)]
+enum ParseMode {
+    Lenient,
+    Strict,
 }
 
-fn valid_expiration_year(test: &str) -> bool {
-    test_range_number(test, 4, 2020, 2030)
+/// Something wrong with one field of a record.
+enum Problem {
+    Missing { field: String },
+    Invalid { field: String, value: String, rule: String },
+    /// Only reported in strict mode.
+    Unknown { field: String },
+    /// Only reported in strict mode.
+    Warning(Warning),
 }
 
-fn valid_pid(test: &str) -> bool {
-    test_range_number(test, 9, -1, 999999999)
-}
+impl Passport {
+    fn new() -> Self {
+        Passport { fields: vec![], extras: BTreeMap::new(), warnings: vec![], lines: (0, 0) }
+    }
+
+    /// Adds a field read on the record's current last line.
+    fn set_value(&mut self, schema: &Schema, key: &str, value: &str) {
+        let exists = if schema.field(key).is_some() {
+            self.fields.iter().any(|(name, _)| name == key)
+        } else {
+            self.extras.contains_key(key)
+        };
+        if exists {
+            self.warnings.push(Warning::Duplicate { field: key.to_string(), value: value.to_string(), line: self.lines.1 });
+        } else if schema.field(key).is_some() {
+            self.fields.push((key.to_string(), value.to_string()));
+        } else {
+            self.extras.insert(key.to_string(), value.to_string());
+        }
+    }
+
+    /// Adds a `key:value` token, warning about anything else.
+    fn add_token(&mut self, schema: &Schema, token: &str) {
+        match token.split_once(':') {
+            Some((key, value)) if !key.is_empty() => self.set_value(schema, key, value),
+            _ => self.warnings.push(Warning::Malformed { token: token.to_string(), line: self.lines.1 }),
+        }
+    }
+
+    /// The field's value, empty if it was not given.
+    fn get(&self, key: &str) -> &str {
+        self.fields.iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value.as_str())
+            .unwrap_or_default()
+    }
+
+    /// The required fields that are absent or empty.
+    fn whats_missing(&self, schema: &Schema) -> Vec<String> {
+        schema.fields.iter()
+            .filter(|field| field.required && self.get(&field.name).is_empty())
+            .map(|field| field.name.clone())
+            .collect()
+    }
+
+    fn read_passports(path: &str, schema: &Schema) -> Result<Vec<Passport>> {
+        let passport_file = File::open(path)?;
+        Passport::records(BufReader::new(passport_file), schema).collect()
+    }
+
+    /// Reads records separated by blank lines, each a run of `key:value`
+    /// tokens, one at a time as the iterator is advanced.
+    fn records<R: BufRead>(reader: R, schema: &Schema) -> PassportReader<'_, R> {
+        PassportReader { reader, schema, line: 0, buffer: String::new(), done: false }
+    }
+
+    fn is_cursory_valid(&self, schema: &Schema) -> bool {
+        self.whats_missing(schema).is_empty()
+    }
+
+    /// Every missing required field and every given field that breaks its
+    /// rule, in schema order.  Strict mode adds unknown fields and warnings.
+    fn problems(&self, schema: &Schema, mode: ParseMode) -> Vec<Problem> {
+        let mut result: Vec<Problem> = vec![];
+        for field in &schema.fields {
+            let value = self.get(&field.name);
+            if value.is_empty() {
+                if field.required {
+                    result.push(Problem::Missing { field: field.name.clone() });
+                }
+            } else if !field.rule.check(value) {
+                result.push(Problem::Invalid {
+                    field: field.name.clone(),
+                    value: value.to_string(),
+                    rule: field.rule.to_string(),
+                });
+            }
+        }
+
+        if mode == ParseMode::Strict {
+            result.extend(self.extras.keys().map(|field| Problem::Unknown { field: field.clone() }));
+            result.extend(self.warnings.iter().cloned().map(Problem::Warning));
+        }
+        result
+    }
 
-fn valid_height(test: &str) -> bool {
-    if test.trim().ends_with("cm") {
-        let height_cm:i32 = test.trim().trim_end_matches("cm").parse().unwrap_or_default();
-        return height_cm >= 150 && height_cm <= 193;
-    } else if test.trim().ends_with("in") {
-        let height_in:i32 = test.trim().trim_end_matches("in").parse().unwrap_or_default();
-        return height_in >= 59 && height_in < 76
+    /// Whether every required field is present and every field given
+    /// follows its rule.  Agrees with `problems` but stops at the first one
+    /// and allocates nothing, since it runs on every record of a batch.
+    fn is_valid(&self, schema: &Schema, mode: ParseMode) -> bool {
+        if mode == ParseMode::Strict && (!self.extras.is_empty() || !self.warnings.is_empty()) {
+            return false;
+        }
+        schema.fields.iter().all(|field| {
+            let value = self.get(&field.name);
+            if value.is_empty() {
+                !field.required
+            } else {
+                field.rule.check(value)
+            }
+        })
     }
-    false
 }
 
-fn valid_hair_color(test: &str) -> bool {
-    let re = Regex::new("#[0-9a-f]{6}").unwrap();
-    re.is_match(test)
+/// Reads a batch in the named format, sorting fields by the schema.
+fn load_batch(path: &str, format: &str, schema: &Schema) -> std::result::Result<Vec<Passport>, String> {
+    let with_path = |error: &dyn fmt::Display| format!("{}: {}", path, error);
+    match format {
+        "batch" => Passport::read_passports(path, schema).map_err(|error| with_path(&error)),
+        "json" | "csv" => {
+            let reader = BufReader::new(File::open(path).map_err(|error| with_path(&error))?);
+            let read = if format == "json" { formats::read_json_lines(reader, schema) } else { formats::read_csv(reader, schema) };
+            read.map_err(|error| with_path(&error))
+        },
+        other => Err(format!("Unknown input format '{}', expected batch, json or csv", other)),
+    }
 }
 
-fn valid_eye_color(test: &str) -> bool {
-    match test.trim() {
-        "amb" | "blu" | "brn" | "gry" | "grn" | "hzl" | "oth" => true,
-        _ => false,
+/// Validates the batch under each rule set, printing the counts for each
+/// and then every record the rule sets disagree on.  The batch is read once
+/// per rule set since each decides which fields it knows.
+fn compare_profiles(path: &str, format: &str, profiles: &[(String, Schema)], mode: ParseMode) -> std::result::Result<(), String> {
+    let width = profiles.iter().map(|(name, _)| name.len()).max().unwrap_or(0).max(7);
+    let mut verdicts: Vec<Vec<bool>> = vec![];
+    let mut lines: Vec<(usize, usize)> = vec![];
+    println!("{:<width$}  {:>7}  {:>5}", "profile", "cursory", "valid", width = width);
+    for (name, schema) in profiles {
+        let batch = load_batch(path, format, schema)?;
+        let cursory = batch.iter().filter(|passport| passport.is_cursory_valid(schema)).count();
+        let valid: Vec<bool> = batch.iter().map(|passport| passport.is_valid(schema, mode)).collect();
+        println!("{:<width$}  {:>7}  {:>5}", name, cursory, valid.iter().filter(|valid| **valid).count(), width = width);
+
+        lines = batch.iter().map(|passport| passport.lines).collect();
+        verdicts.push(valid);
+    }
+
+    let disagreements: Vec<usize> = (0..lines.len())
+        .filter(|idx| verdicts.iter().any(|valid| valid[*idx] != verdicts[0][*idx]))
+        .collect();
+    if disagreements.is_empty() {
+        println!("The profiles agree on every record");
+        return Ok(());
     }
+
+    println!();
+    let names: Vec<String> = profiles.iter().map(|(name, _)| format!("{:<width$}", name, width = width)).collect();
+    println!("{:>6}  {:<9}  {}", "record", "lines", names.join("  ").trim_end());
+    for idx in disagreements {
+        let cells: Vec<String> = verdicts.iter()
+            .map(|valid| format!("{:<width$}", if valid[idx] { "valid" } else { "invalid" }, width = width))
+            .collect();
+        println!("{:>6}  {:<9}  {}", idx + 1, format!("{}-{}", lines[idx].0, lines[idx].1), cells.join("  ").trim_end());
+    }
+    Ok(())
 }
 
-impl Passport {
-    fn new() -> Self {
-        Passport {
-            ecl: String::from(""),
-            pid: String::from(""),
-            eyr: String::from(""),
-            hcl: String::from(""),
-            byr: String::from(""),
-            iyr: String::from(""),
-            cid: String::from(""),
-            hgt: String::from(""),
-        }
-    }
-
-    fn set_value(&mut self, key: &str, value: &str) {
-        match key {
-            "ecl" => self.ecl = value.to_string(),
-            "pid" => self.pid = value.to_string(),
-            "eyr" => self.eyr = value.to_string(),
-            "hcl" => self.hcl = value.to_string(),
-            "byr" => self.byr = value.to_string(),
-            "iyr" => self.iyr = value.to_string(),
-            "cid" => self.cid = value.to_string(),
-            "hgt" => self.hgt = value.to_string(),
-            &_ => {}
-        }
-    }
-
-    fn whats_missing(&self) -> Vec<String> {
-        let mut result: Vec<String> = vec![];
-        if self.byr.is_empty() { result.push(String::from("byr")) }
-        if self.iyr.is_empty() { result.push(String::from("iyr")) }
-        if self.eyr.is_empty() { result.push(String::from("eyr")) }
-        if self.hgt.is_empty() { result.push(String::from("hgt")) }
-        if self.hcl.is_empty() { result.push(String::from("hcl")) }
-        if self.ecl.is_empty() { result.push(String::from("ecl")) }
-        if self.pid.is_empty() { result.push(String::from("pid")) }
-        if self.cid.is_empty() { result.push(String::from("cid")) }
+/// Writes a synthesized batch to `path` and its manifest beside it as
+/// `<path>.manifest.csv`.
+fn synthesize(path: &str, schema: &Schema, seed: u64, valid: usize, each: usize) -> std::result::Result<(), String> {
+    let cases = generator::Generator::new(seed).batch(schema, valid, each)?;
+    let manifest_path = format!("{}.manifest.csv", path);
+    let create = |path: &str| File::create(path).map(std::io::BufWriter::new).map_err(|error| format!("{}: {}", path, error));
 
-        result
+    let passports: Vec<Passport> = cases.iter().map(|case| case.passport.clone()).collect();
+    formats::write_batch(&mut create(path)?, &passports).map_err(|error| format!("{}: {}", path, error))?;
+    generator::write_manifest(&mut create(&manifest_path)?, &cases).map_err(|error| format!("{}: {}", manifest_path, error))?;
+
+    let expected_valid = cases.iter().filter(|case| case.valid).count();
+    println!("Wrote {} records ({} should pass) to {} and {}", cases.len(), expected_valid, path, manifest_path);
+    Ok(())
+}
+
+/// Validates the batch and prints every record whose outcome differs from
+/// the manifest's.
+fn check_manifest(batch: &[Passport], schema: &Schema, mode: ParseMode, manifest_path: &str) -> std::result::Result<(), String> {
+    let file = File::open(manifest_path).map_err(|error| format!("{}: {}", manifest_path, error))?;
+    let expectations = generator::read_manifest(BufReader::new(file)).map_err(|error| format!("{}: {}", manifest_path, error))?;
+    if expectations.len() != batch.len() {
+        return Err(format!("{} describes {} records but the batch has {}", manifest_path, expectations.len(), batch.len()));
     }
 
-    fn read_passports(path: &str) -> Result<Vec<Passport>> {
-        let mut result: Vec<Passport> = vec![];
+    let mut mismatches = 0;
+    for expectation in &expectations {
+        let passport = batch.get(expectation.record.wrapping_sub(1))
+            .ok_or_else(|| format!("{} names record {} which isn't in the batch", manifest_path, expectation.record))?;
+        let valid = passport.is_valid(schema, mode);
+        if valid != expectation.valid {
+            mismatches += 1;
+            println!("Record {} ({}): expected {}, got {}", expectation.record, expectation.description,
+                if expectation.valid { "valid" } else { "invalid" }, if valid { "valid" } else { "invalid" });
+        }
+    }
+    println!("{} of {} records match the manifest", expectations.len() - mismatches, expectations.len());
+    Ok(())
+}
+
+/// Validates `count` records, cycling through the batch, and prints the
+/// throughput.
+fn run_bench(batch: &[Passport], schema: &Schema, mode: ParseMode, count: usize) {
+    if batch.is_empty() {
+        println!("Nothing to validate");
+        return;
+    }
+
+    let start = std::time::Instant::now();
+    let valid = batch.iter().cycle().take(count).filter(|passport| passport.is_valid(schema, mode)).count();
+    let elapsed = start.elapsed().as_secs_f64();
+    println!("Validated {} passports ({} valid) in {:.3}s, {:.0} passports/s",
+        count, valid, elapsed, count as f64 / elapsed.max(f64::EPSILON));
+}
+
+/// Yields each record of a batch file as soon as its last line is read.
+/// Any number of blank lines separate records, lines may end in CRLF, and a
+/// read error ends the iteration after it is yielded.
+struct PassportReader<'a, R> {
+    reader: R,
+    schema: &'a Schema,
+    line: usize,
+    buffer: String,
+    done: bool,
+}
+
+impl<R: BufRead> Iterator for PassportReader<'_, R> {
+    type Item = Result<Passport>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
 
-        let passport_file = File::open(path)?;
-        let reader = BufReader::new(passport_file);
-        
         let mut p = Passport::new();
-        for line in reader.lines() {
-            if let Ok(raw_line) = line {
-                if raw_line.trim().is_empty() {
-                    result.push(p);
-                    p = Passport::new();
-                } else {
-                    let parts_iter = raw_line.split_whitespace();
-                    for part in parts_iter {
-                        let mut key_value = part.split(':');
-                        p.set_value(key_value.next().unwrap_or_default(), key_value.next().unwrap_or_default());
+        loop {
+            self.buffer.clear();
+            match self.reader.read_line(&mut self.buffer) {
+                Ok(0) => {
+                    self.done = true;
+                    // A record only exists once it has a line of fields.
+                    return if p.lines.0 == 0 { None } else { Some(Ok(p)) };
+                },
+                Ok(_) => {
+                    self.line += 1;
+                    let text = self.buffer.trim_end_matches(['\n', '\r']);
+                    if text.trim().is_empty() {
+                        if p.lines.0 != 0 {
+                            return Some(Ok(p));
+                        }
+                        continue;
                     }
-                }
-            } else {
-                result.push(p);
-                p = Passport::new();
+
+                    if p.lines.0 == 0 {
+                        p.lines.0 = self.line;
+                    }
+                    p.lines.1 = self.line;
+                    for part in text.split_whitespace() {
+                        p.add_token(self.schema, part);
+                    }
+                },
+                Err(error) => {
+                    self.done = true;
+                    return Some(Err(error));
+                },
             }
         }
-        result.push(p);
+    }
+}
 
-        Ok(result)
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let mut path = String::from("problem.txt");
+    let mut profile_names: Vec<String> = vec![];
+    let mut schema_paths: Vec<String> = vec![];
+    let mut year = schema::current_year();
+    let mut report: Option<String> = None;
+    let mut show_typed = false;
+    let mut mode = ParseMode::Lenient;
+    let mut from = String::from("batch");
+    let mut to: Option<String> = None;
+    let mut bench: Option<usize> = None;
+    let mut synthesize_path: Option<String> = None;
+    let mut manifest: Option<String> = None;
+    let (mut seed, mut valid, mut each) = (0u64, 100usize, 1usize);
+    let mut idx = 0;
+    while idx < args.len() {
+        match args[idx].as_str() {
+            "--schema" => {
+                idx += 1;
+                schema_paths.push(args.get(idx).expect("--schema needs a schema file").to_string());
+            },
+            "--profile" => {
+                idx += 1;
+                profile_names.push(args.get(idx).expect("--profile needs a rule set name").to_string());
+            },
+            "--year" => {
+                idx += 1;
+                year = args.get(idx).and_then(|text| text.parse().ok()).expect("--year needs a year");
+            },
+            "--report" => {
+                idx += 1;
+                report = Some(args.get(idx).expect("--report needs a format (table or json)").to_string());
+            },
+            "--typed" => show_typed = true,
+            "--strict" => mode = ParseMode::Strict,
+            "--bench" => {
+                idx += 1;
+                bench = Some(args.get(idx).and_then(|text| text.parse().ok()).expect("--bench needs a count"));
+            },
+            "--from" => {
+                idx += 1;
+                from = args.get(idx).expect("--from needs a format (batch, json or csv)").to_string();
+            },
+            "--to" => {
+                idx += 1;
+                to = Some(args.get(idx).expect("--to needs a format (batch, json or csv)").to_string());
+            },
+            "--synthesize" => {
+                idx += 1;
+                synthesize_path = Some(args.get(idx).expect("--synthesize needs an output file").to_string());
+            },
+            "--seed" => {
+                idx += 1;
+                seed = args.get(idx).and_then(|text| text.parse().ok()).expect("--seed needs a number");
+            },
+            "--valid" => {
+                idx += 1;
+                valid = args.get(idx).and_then(|text| text.parse().ok()).expect("--valid needs a count");
+            },
+            "--each" => {
+                idx += 1;
+                each = args.get(idx).and_then(|text| text.parse().ok()).expect("--each needs a count");
+            },
+            "--check" => {
+                idx += 1;
+                manifest = Some(args.get(idx).expect("--check needs a manifest file").to_string());
+            },
+            other => path = other.to_string(),
+        }
+        idx += 1;
     }
 
-    fn is_cursory_valid(&self) -> bool {
-        !self.byr.is_empty() && !self.iyr.is_empty() && !self.eyr.is_empty() && !self.hgt.is_empty() && !self.hcl.is_empty() && !self.pid.is_empty() && !self.ecl.is_empty()
+    if profile_names.is_empty() && schema_paths.is_empty() {
+        profile_names.push(Schema::profile_names()[0].to_string());
+    }
+    let mut profiles: Vec<(String, Schema)> = vec![];
+    for name in profile_names {
+        match Schema::profile(&name, year) {
+            Some(schema) => profiles.push((name, schema)),
+            None => {
+                println!("Unknown profile '{}', expected one of {}", name, Schema::profile_names().join(", "));
+                return;
+            },
+        }
+    }
+    for schema_path in schema_paths {
+        match Schema::read(&schema_path, year) {
+            Ok(schema) => profiles.push((schema_path, schema)),
+            Err(error) => {
+                println!("{}: {}", schema_path, error);
+                return;
+            },
+        }
     }
 
-    fn is_valid(&self) -> bool {
-        valid_birth_year(&self.byr) && valid_issue_year(&self.iyr) && valid_expiration_year(&self.eyr) && valid_pid(&self.pid) &&
-        valid_height(&self.hgt) && valid_hair_color(&self.hcl) && valid_eye_color(&self.ecl)
+    if profiles.len() > 1 {
+        if let Err(error) = compare_profiles(&path, &from, &profiles, mode) {
+            println!("{}", error);
+        }
+        return;
+    }
+    let schema = profiles.remove(0).1;
+
+    if let Some(out) = synthesize_path {
+        if let Err(error) = synthesize(&out, &schema, seed, valid, each) {
+            println!("{}", error);
+        }
+        return;
+    }
+
+    let batch = match load_batch(&path, &from, &schema) {
+        Ok(batch) => batch,
+        Err(error) => {
+            println!("{}", error);
+            return;
+        },
+    };
+
+    if let Some(manifest_path) = manifest {
+        if let Err(error) = check_manifest(&batch, &schema, mode, &manifest_path) {
+            println!("{}", error);
+        }
+        return;
+    }
+
+    if let Some(count) = bench {
+        run_bench(&batch, &schema, mode, count);
+        return;
+    }
+
+    if let Some(format) = to {
+        let mut out = std::io::BufWriter::new(std::io::stdout());
+        let written = match format.as_str() {
+            "batch" => formats::write_batch(&mut out, &batch),
+            "json" => formats::write_json_lines(&mut out, &batch),
+            "csv" => formats::write_csv(&mut out, &batch, &schema),
+            other => {
+                println!("Unknown output format '{}', expected batch, json or csv", other);
+                return;
+            },
+        };
+        if let Err(error) = written {
+            eprintln!("Failed to write: {}", error);
+        }
+        return;
+    }
+    if let Some(format) = report {
+        let results = report::validate(&batch, &schema, mode);
+        match format.as_str() {
+            "table" => report::print_table(&results),
+            "json" => println!("{}", report::to_json(&results)),
+            other => println!("Unknown report format '{}', expected table or json", other),
+        }
+        return;
+    }
+
+    if show_typed {
+        let missing: Vec<&str> = ValidPassport::FIELDS.iter().filter(|name| schema.field(name).is_none()).copied().collect();
+        if !missing.is_empty() {
+            println!("--typed needs a schema with rules for {}", missing.join(", "));
+            return;
+        }
+
+        let valid: Vec<ValidPassport> = batch.iter().filter_map(|passport| ValidPassport::try_from((passport, &schema)).ok()).collect();
+        for passport in &valid {
+            println!("{}  ({:.1} cm)", passport, passport.height.in_cm());
+        }
+        if let Some(tallest) = valid.iter().max_by(|a, b| a.height.in_cm().total_cmp(&b.height.in_cm())) {
+            println!("{} typed passports, tallest {:.1} cm (pid {})", valid.len(), tallest.height.in_cm(), tallest.passport_id.as_str());
+        }
+        return;
+    }
+
+    if mode == ParseMode::Lenient {
+        for warning in batch.iter().flat_map(|passport| &passport.warnings) {
+            println!("Warning, {}", warning);
+        }
     }
-}
 
-fn main() {
-    let batch = Passport::read_passports("problem.txt").unwrap();
     let mut cursory_valid_count = 0;
     let mut valid_count = 0;
     for passport in batch {
-        if passport.is_cursory_valid() {
+        if passport.is_cursory_valid(&schema) {
             cursory_valid_count += 1;
-            if passport.is_valid() {
+            if passport.is_valid(&schema, mode) {
                 valid_count += 1;
             }
         } 
@@ -160,32 +527,127 @@ fn main() {
 mod tests {
     use super::*;
 
+    pub(crate) fn passport(schema: &Schema, text: &str) -> Passport {
+        let mut p = Passport::new();
+        for part in text.split_whitespace() {
+            let (key, value) = part.split_once(':').unwrap();
+            p.set_value(schema, key, value);
+        }
+        p
+    }
+
     #[test] 
     fn check_expected_valid() {
-        let p = Passport {
-            ecl: String::from("gry"),
-            pid: String::from("860033327"),
-            eyr: String::from("2020"),
-            hcl: String::from("#fffffd"),
-            byr: String::from("1937"),
-            iyr: String::from("2017"),
-            cid: String::from("147"),
-            hgt: String::from("183cm"),
-        };
+        let schema = Schema::profile("north-pole", 2020).unwrap();
+        let p = passport(&schema, "ecl:gry pid:860033327 eyr:2020 hcl:#fffffd byr:1937 iyr:2017 cid:147 hgt:183cm");
 
-        assert!(p.is_cursory_valid());
-
-        let p = Passport {
-            ecl: String::from("gry"),
-            pid: String::from("860033327"),
-            eyr: String::from("2020"),
-            hcl: String::from("#fffffd"),
-            byr: String::from("1937"),
-            iyr: String::from("2017"),
-            cid: String::from(""),
-            hgt: String::from("183cm"),
-        };
+        assert!(p.is_cursory_valid(&schema));
+        assert!(p.is_valid(&schema, ParseMode::Lenient));
+
+        let p = passport(&schema, "ecl:gry pid:860033327 eyr:2020 hcl:#fffffd byr:1937 iyr:2017 cid: hgt:183cm");
+
+        assert!(p.is_cursory_valid(&schema));
+
+        let p = passport(&schema, "ecl:gry eyr:2020 hcl:#fffffd byr:1937 iyr:2017 cid:147 hgt:183cm");
+        assert_eq!(p.whats_missing(&schema), vec!["pid"]);
+
+        let p = passport(&schema, "ecl:gry pid:860033327 eyr:2020 hcl:#fffffd byr:1937 iyr:2017 hgt:183in");
+        assert!(p.is_cursory_valid(&schema));
+        assert!(!p.is_valid(&schema, ParseMode::Lenient));
+
+        // Heights in inches stop below 76in.
+        let p = passport(&schema, "ecl:gry pid:860033327 eyr:2020 hcl:#fffffd byr:1937 iyr:2017 hgt:75in");
+        assert!(p.is_valid(&schema, ParseMode::Lenient));
+        let p = passport(&schema, "ecl:gry pid:860033327 eyr:2020 hcl:#fffffd byr:1937 iyr:2017 hgt:76in");
+        assert!(!p.is_valid(&schema, ParseMode::Lenient));
+
+        let p = passport(&schema, "ecl:red pid:860033327 eyr:2020 byr:1937 iyr:2017 hgt:183in");
+        let problems: Vec<String> = p.problems(&schema, ParseMode::Lenient).iter()
+            .map(|problem| match problem {
+                Problem::Missing { field } => format!("{} missing", field),
+                Problem::Invalid { field, value, rule } => format!("{} {} breaks {}", field, value, rule),
+                _ => String::from("unexpected"),
+            })
+            .collect();
+        assert_eq!(problems, vec![
+            "hgt 183in breaks units cm:150..193 in:59..75",
+            "hcl missing",
+            "ecl red breaks one-of amb blu brn gry grn hzl oth",
+        ]);
+    }
+
+    #[test]
+    fn check_unknown_and_duplicate_fields() {
+        let schema = Schema::profile("north-pole", 2020).unwrap();
+        let mut p = Passport::new();
+        p.lines = (1, 1);
+        for token in "ecl:gry pid:860033327 eyr:2020 hcl:#fffffd byr:1937 iyr:2017 hgt:183cm".split_whitespace() {
+            p.add_token(&schema, token);
+        }
+        assert!(p.is_valid(&schema, ParseMode::Strict));
+
+        p.lines.1 = 2;
+        for token in "pet:reindeer ecl:blu stamp : :x".split_whitespace() {
+            p.add_token(&schema, token);
+        }
+        assert_eq!(p.get("ecl"), "gry");
+        assert_eq!(p.extras.get("pet").map(|pet| pet.as_str()), Some("reindeer"));
+        assert_eq!(p.warnings, vec![
+            Warning::Duplicate { field: String::from("ecl"), value: String::from("blu"), line: 2 },
+            Warning::Malformed { token: String::from("stamp"), line: 2 },
+            Warning::Malformed { token: String::from(":"), line: 2 },
+            Warning::Malformed { token: String::from(":x"), line: 2 },
+        ]);
+        assert!(p.is_valid(&schema, ParseMode::Lenient));
+        assert!(!p.is_valid(&schema, ParseMode::Strict));
+        assert!(p.problems(&schema, ParseMode::Lenient).is_empty());
+        assert_eq!(p.problems(&schema, ParseMode::Strict).len(), 5);
+    }
+
+    #[test]
+    fn check_is_valid_matches_problems() {
+        let schema = Schema::profile("north-pole", 2020).unwrap();
+        let batch: Vec<Passport> = Passport::records(include_str!("../problem.txt").as_bytes(), &schema)
+            .collect::<Result<_>>()
+            .unwrap();
+        for mode in [ParseMode::Lenient, ParseMode::Strict] {
+            for passport in &batch {
+                assert_eq!(passport.is_valid(&schema, mode), passport.problems(&schema, mode).is_empty(), "{:?}", passport);
+            }
+        }
+        assert_eq!(batch.iter().filter(|passport| passport.is_valid(&schema, ParseMode::Lenient)).count(), 114);
+    }
+
+    /// A reader that fails, to show records are read no further than needed.
+    struct Failing;
+
+    impl std::io::Read for Failing {
+        fn read(&mut self, _: &mut [u8]) -> Result<usize> {
+            Err(std::io::Error::other("disk on fire"))
+        }
+    }
+
+    #[test]
+    fn check_streaming_records() {
+        let schema = Schema::profile("north-pole", 2020).unwrap();
+        let text = "\r\n\nbyr:1937 iyr:2017\r\necl:gry\r\n\r\n\r\n  \n\nbyr:1940\n\n\n";
+        let batch: Vec<Passport> = Passport::records(text.as_bytes(), &schema).collect::<Result<_>>().unwrap();
+        assert_eq!(batch.len(), 2);
+        assert_eq!(batch[0].lines, (3, 4));
+        assert_eq!(batch[0].get("ecl"), "gry");
+        assert_eq!(batch[1].lines, (9, 9));
+        assert!(batch.iter().all(|passport| passport.warnings.is_empty()));
+
+        let unterminated = Passport::records("byr:1937".as_bytes(), &schema).collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(unterminated.len(), 1);
+        assert_eq!(Passport::records("\n\n".as_bytes(), &schema).count(), 0);
+
+        let mut records = Passport::records(BufReader::new(std::io::Read::chain("byr:1937\n\n".as_bytes(), Failing)), &schema);
+        assert_eq!(records.next().unwrap().unwrap().get("byr"), "1937");
+        assert!(records.next().unwrap().is_err());
+        assert!(records.next().is_none());
 
-        assert!(p.is_cursory_valid());
+        let mut records = Passport::records(&b"byr:19\xff37\n"[..], &schema);
+        assert!(records.next().unwrap().is_err());
     }
 }
@@ -0,0 +1,103 @@
+use crate::formats::json_string;
+use crate::schema::Schema;
+use crate::{ParseMode, Passport, Problem, Warning};
+
+/// The problems found in one record of a batch.  `record` counts from 1.
+pub struct RecordReport {
+    pub record: usize,
+    pub lines: (usize, usize),
+    pub problems: Vec<Problem>,
+}
+
+pub fn validate(batch: &[Passport], schema: &Schema, mode: ParseMode) -> Vec<RecordReport> {
+    batch.iter()
+        .enumerate()
+        .map(|(idx, passport)| RecordReport {
+            record: idx + 1,
+            lines: passport.lines,
+            problems: passport.problems(schema, mode),
+        })
+        .collect()
+}
+
+fn describe_lines(lines: (usize, usize)) -> String {
+    match lines {
+        (0, _) => String::from("-"),
+        (first, last) if first == last => first.to_string(),
+        (first, last) => format!("{}-{}", first, last),
+    }
+}
+
+/// Prints a row per problem, leaving out records that passed.
+pub fn print_table(results: &[RecordReport]) {
+    println!("{:>6}  {:<9}  {:<5}  problem", "record", "lines", "field");
+    for result in results {
+        for problem in &result.problems {
+            let (field, text) = match problem {
+                Problem::Missing { field } => (field, String::from("missing")),
+                Problem::Invalid { field, value, rule } => (field, format!("'{}' breaks {}", value, rule)),
+                Problem::Unknown { field } => (field, String::from("not in the schema")),
+                Problem::Warning(Warning::Duplicate { field, value, line }) =>
+                    (field, format!("given again as '{}' on line {}", value, line)),
+                Problem::Warning(Warning::Malformed { token, line }) =>
+                    (token, format!("not key:value on line {}", line)),
+            };
+            println!("{:>6}  {:<9}  {:<5}  {}", result.record, describe_lines(result.lines), field, text);
+        }
+    }
+
+    let valid = results.iter().filter(|result| result.problems.is_empty()).count();
+    println!("{} of {} records valid", valid, results.len());
+}
+
+/// The report as a JSON object with the record counts and an entry for
+/// each record that failed.
+pub fn to_json(results: &[RecordReport]) -> String {
+    let invalid: Vec<String> = results.iter()
+        .filter(|result| !result.problems.is_empty())
+        .map(|result| {
+            let problems: Vec<String> = result.problems.iter()
+                .map(|problem| match problem {
+                    Problem::Missing { field } => format!("{{\"field\":{},\"problem\":\"missing\"}}", json_string(field)),
+                    Problem::Invalid { field, value, rule } => format!(
+                        "{{\"field\":{},\"problem\":\"invalid\",\"value\":{},\"rule\":{}}}",
+                        json_string(field), json_string(value), json_string(rule)),
+                    Problem::Unknown { field } => format!("{{\"field\":{},\"problem\":\"unknown\"}}", json_string(field)),
+                    Problem::Warning(Warning::Duplicate { field, value, line }) => format!(
+                        "{{\"field\":{},\"problem\":\"duplicate\",\"value\":{},\"line\":{}}}",
+                        json_string(field), json_string(value), line),
+                    Problem::Warning(Warning::Malformed { token, line }) => format!(
+                        "{{\"token\":{},\"problem\":\"malformed\",\"line\":{}}}", json_string(token), line),
+                })
+                .collect();
+            format!("{{\"record\":{},\"first_line\":{},\"last_line\":{},\"problems\":[{}]}}",
+                result.record, result.lines.0, result.lines.1, problems.join(","))
+        })
+        .collect();
+
+    format!("{{\"records\":{},\"valid\":{},\"invalid\":[{}]}}",
+        results.len(), results.len() - invalid.len(), invalid.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_json_report() {
+        let schema = Schema::parse_at("name required pattern ^[a-z]+$\nage optional number 2 10..99\n", 2020).unwrap();
+        let mut first = Passport::new();
+        first.set_value(&schema, "name", "elf");
+        first.lines = (1, 1);
+        let mut second = Passport::new();
+        second.set_value(&schema, "age", "9\"");
+        second.lines = (3, 4);
+
+        let results = validate(&[first, second], &schema, ParseMode::Lenient);
+        assert_eq!(to_json(&results), concat!(
+            "{\"records\":2,\"valid\":1,\"invalid\":[{\"record\":2,\"first_line\":3,\"last_line\":4,\"problems\":[",
+            "{\"field\":\"name\",\"problem\":\"missing\"},",
+            "{\"field\":\"age\",\"problem\":\"invalid\",\"value\":\"9\\\"\",\"rule\":\"number 2 10..99\"}]}]}",
+        ));
+    }
+}
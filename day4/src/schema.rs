@@ -0,0 +1,301 @@
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use regex::Regex;
+
+/// The built-in rule sets by name.  The first is used when no other is given.
+const PROFILES: [(&str, &str); 3] = [
+    ("north-pole", include_str!("../profiles/north-pole.schema")),
+    ("passport", include_str!("../profiles/passport.schema")),
+    ("travel", include_str!("../profiles/travel.schema")),
+];
+
+/// The year today, in UTC.
+pub fn current_year() -> i64 {
+    let days = SystemTime::now().duration_since(UNIX_EPOCH).map(|since| since.as_secs() / 86_400).unwrap_or(0) as i64;
+    // Converts days since 1970-01-01 to a civil date, as in Howard Hinnant's
+    // `civil_from_days`, keeping only the year.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let march_month = (5 * day_of_year + 2) / 153;
+    // Years in this scheme start in March, so January and February belong
+    // to the next one.
+    year_of_era + era * 400 + if march_month >= 10 { 1 } else { 0 }
+}
+
+/// What a field's value must look like.
+pub enum Rule {
+    /// Anything will do.
+    Any,
+    /// Exactly `digits` characters forming a number from `min` to `max`.
+    Number { digits: usize, min: i64, max: i64 },
+    /// A number followed by one of the suffixes, each with its own range.
+    Units(Vec<Unit>),
+    /// A regex that must match the whole value.  `text` is the pattern as
+    /// written; `regex` is compiled once, anchored at both ends.
+    Pattern { text: String, regex: Regex },
+    OneOf(Vec<String>),
+}
+
+/// A unit suffix such as `cm` and the inclusive range allowed with it.
+pub struct Unit {
+    pub suffix: String,
+    pub min: i64,
+    pub max: i64,
+}
+
+impl Rule {
+    pub fn check(&self, value: &str) -> bool {
+        let value = value.trim();
+        match self {
+            Rule::Any => true,
+            Rule::Number { digits, min, max } => value.len() == *digits && in_range(value, *min, *max),
+            Rule::Units(units) => units.iter().any(|unit| {
+                value.strip_suffix(unit.suffix.as_str()).is_some_and(|number| in_range(number, unit.min, unit.max))
+            }),
+            Rule::Pattern { regex, .. } => regex.is_match(value),
+            Rule::OneOf(values) => values.iter().any(|allowed| allowed == value),
+        }
+    }
+
+    fn parse(words: &[&str], rest: &str, year: i64) -> Result<Rule, String> {
+        let rule = match words.first() {
+            None => Rule::Any,
+            Some(&"number") => {
+                if words.len() != 3 {
+                    return Err(String::from("number needs a digit count and a range"));
+                }
+                let digits = words[1].parse().map_err(|_| format!("'{}' is not a digit count", words[1]))?;
+                let (min, max) = parse_range(words[2], year)?;
+                Rule::Number { digits, min, max }
+            },
+            Some(&"units") => {
+                let units = words[1..].iter()
+                    .map(|word| {
+                        let (suffix, range) = word.split_once(':').ok_or_else(|| format!("'{}' is not like cm:150..193", word))?;
+                        let (min, max) = parse_range(range, year)?;
+                        Ok(Unit { suffix: suffix.to_string(), min, max })
+                    })
+                    .collect::<Result<Vec<Unit>, String>>()?;
+                if units.is_empty() {
+                    return Err(String::from("units needs at least one unit"));
+                }
+                Rule::Units(units)
+            },
+            Some(&"pattern") => {
+                let pattern = rest.trim_start().strip_prefix("pattern").unwrap_or_default().trim();
+                if pattern.is_empty() {
+                    return Err(String::from("pattern needs a regular expression"));
+                }
+                let regex = Regex::new(&format!("^(?:{})$", pattern)).map_err(|error| error.to_string())?;
+                Rule::Pattern { text: pattern.to_string(), regex }
+            },
+            Some(&"one-of") => {
+                if words.len() < 2 {
+                    return Err(String::from("one-of needs at least one value"));
+                }
+                Rule::OneOf(words[1..].iter().map(|word| word.to_string()).collect())
+            },
+            Some(other) => return Err(format!("Unknown rule '{}', expected number, units, pattern or one-of", other)),
+        };
+        Ok(rule)
+    }
+}
+
+impl fmt::Display for Rule {
+    /// Writes the rule as it appears in a schema file.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rule::Any => f.write_str("any"),
+            Rule::Number { digits, min, max } => write!(f, "number {} {}..{}", digits, min, max),
+            Rule::Units(units) => {
+                f.write_str("units")?;
+                for unit in units {
+                    write!(f, " {}:{}..{}", unit.suffix, unit.min, unit.max)?;
+                }
+                Ok(())
+            },
+            Rule::Pattern { text, .. } => write!(f, "pattern {}", text),
+            Rule::OneOf(values) => write!(f, "one-of {}", values.join(" ")),
+        }
+    }
+}
+
+/// Whether the text is plain digits, with no sign, in the range.
+fn in_range(number: &str, min: i64, max: i64) -> bool {
+    !number.is_empty()
+        && number.bytes().all(|b| b.is_ascii_digit())
+        && number.parse::<i64>().is_ok_and(|number| number >= min && number <= max)
+}
+
+/// Reads an inclusive range written `min..max`, where either bound may be
+/// `year`, `year+N` or `year-N`.
+fn parse_range(text: &str, year: i64) -> Result<(i64, i64), String> {
+    let bad = || format!("'{}' is not a range like 1920..2002 or year..year+10", text);
+    let bound = |bound: &str| -> Option<i64> {
+        match bound.strip_prefix("year") {
+            Some("") => Some(year),
+            Some(offset) => match offset.strip_prefix('+') {
+                Some(offset) => offset.parse::<i64>().ok().and_then(|offset| year.checked_add(offset)),
+                None => offset.strip_prefix('-')?.parse::<i64>().ok().and_then(|offset| year.checked_sub(offset)),
+            },
+            None => bound.parse().ok(),
+        }
+    };
+    let (min, max) = text.split_once("..").ok_or_else(bad)?;
+    let (min, max) = (bound(min).ok_or_else(bad)?, bound(max).ok_or_else(bad)?);
+    if min > max {
+        return Err(format!("Range {} has min above max", text));
+    }
+    Ok((min, max))
+}
+
+pub struct Field {
+    pub name: String,
+    pub required: bool,
+    pub rule: Rule,
+}
+
+/// The fields a kind of document has and the rules their values follow.
+pub struct Schema {
+    pub fields: Vec<Field>,
+}
+
+impl Schema {
+    /// A built-in rule set, with `year` taken as the current year.
+    pub fn profile(name: &str, year: i64) -> Option<Schema> {
+        let (_, text) = PROFILES.iter().find(|(profile, _)| *profile == name)?;
+        Some(Schema::parse_at(text, year).expect("The built-in profiles are valid"))
+    }
+
+    pub fn profile_names() -> Vec<&'static str> {
+        PROFILES.iter().map(|(name, _)| *name).collect()
+    }
+
+    pub fn read(path: &str, year: i64) -> Result<Schema, SchemaError> {
+        let text = std::fs::read_to_string(path).map_err(|error| SchemaError::new(0, &error.to_string()))?;
+        Schema::parse_at(&text, year)
+    }
+
+    /// Reads a schema with one field per line, as described in
+    /// `profiles/north-pole.schema`, with `year` as the current year.
+    pub fn parse_at(text: &str, year: i64) -> Result<Schema, SchemaError> {
+        let mut fields: Vec<Field> = vec![];
+        for (idx, line) in text.lines().enumerate() {
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+
+            let words: Vec<&str> = trimmed.split_whitespace().collect();
+            let error = |message: &str| SchemaError::new(idx + 1, message);
+            let name = words[0];
+            if fields.iter().any(|field| field.name == name) {
+                return Err(error(&format!("Field '{}' is listed twice", name)));
+            }
+            let required = match words.get(1) {
+                Some(&"required") => true,
+                Some(&"optional") => false,
+                _ => return Err(error("Expected 'required' or 'optional' after the field name")),
+            };
+            // Hand the rule the line after the name and requirement so a
+            // pattern keeps its spaces.
+            let rest = trimmed[name.len()..].trim_start()[words[1].len()..].trim_start();
+            let rule = Rule::parse(&words[2..], rest, year).map_err(|message| error(&message))?;
+
+            fields.push(Field { name: name.to_string(), required, rule });
+        }
+
+        if fields.is_empty() {
+            return Err(SchemaError::new(0, "The schema has no fields"));
+        }
+        Ok(Schema { fields })
+    }
+
+    pub fn field(&self, name: &str) -> Option<&Field> {
+        self.fields.iter().find(|field| field.name == name)
+    }
+}
+
+#[derive(Debug)]
+pub struct SchemaError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl SchemaError {
+    fn new(line: usize, message: &str) -> Self {
+        SchemaError { line, message: message.to_string() }
+    }
+}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Schema line {}: {}", self.line, self.message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_schema_rules() {
+        let schema = Schema::profile("north-pole", 2020).unwrap();
+        let check = |name: &str, value: &str| schema.field(name).unwrap().rule.check(value);
+        assert!(check("byr", "2002"));
+        assert!(!check("byr", "2003"));
+        assert!(!check("byr", "02002"));
+        assert!(check("hgt", "60in"));
+        assert!(check("hgt", "190cm"));
+        assert!(!check("hgt", "190in"));
+        assert!(!check("hgt", "190"));
+        assert!(check("hcl", "#123abc"));
+        assert!(!check("hcl", "#123abz"));
+        assert!(!check("hcl", "#123abcdef"));
+        assert!(!check("hcl", "x#123abc"));
+        assert!(check("ecl", "brn"));
+        assert!(!check("ecl", "wat"));
+        assert!(check("pid", "000000001"));
+        assert!(!check("pid", "0123456789"));
+        assert!(!check("pid", "+00000001"));
+        assert!(!check("hgt", "+60in"));
+        assert!(!check("hgt", "cm"));
+        assert!(!schema.field("cid").unwrap().required);
+        assert_eq!(schema.field("hgt").unwrap().rule.to_string(), "units cm:150..193 in:59..75");
+        assert_eq!(schema.field("hcl").unwrap().rule.to_string(), "pattern #[0-9a-f]{6}");
+
+        let badge = Schema::parse_at("# Elf badges\nname required pattern ^[A-Z][a-z]+ [A-Z][a-z]+$\nlvl optional number 1 1..5\n", 2020).unwrap();
+        assert!(badge.field("name").unwrap().rule.check("Buddy Elf"));
+        assert!(!badge.field("lvl").unwrap().rule.check("6"));
+
+        let error = Schema::parse_at("a required\nb sometimes\n", 2020).err().unwrap();
+        assert_eq!(error.to_string(), "Schema line 2: Expected 'required' or 'optional' after the field name");
+        assert!(Schema::parse_at("a required number 4 10..1", 2020).is_err());
+        assert!(Schema::parse_at("a required units cm", 2020).is_err());
+        assert!(Schema::parse_at("a required\na optional", 2020).is_err());
+    }
+
+    #[test]
+    fn check_profiles() {
+        assert_eq!(Schema::profile_names(), vec!["north-pole", "passport", "travel"]);
+        assert!(Schema::profile("nowhere", 2020).is_none());
+        assert!(!Schema::profile("north-pole", 2020).unwrap().field("cid").unwrap().required);
+        assert!(Schema::profile("passport", 2020).unwrap().field("cid").unwrap().required);
+
+        let travel = Schema::profile("travel", 2026).unwrap();
+        assert_eq!(travel.field("eyr").unwrap().rule.to_string(), "number 4 2026..2036");
+        assert_eq!(travel.field("iyr").unwrap().rule.to_string(), "number 4 2016..2026");
+        assert_eq!(Schema::profile("travel", 2030).unwrap().field("eyr").unwrap().rule.to_string(), "number 4 2030..2040");
+
+        assert!(Schema::parse_at("a required number 4 year+..year", 2020).is_err());
+        assert!(Schema::parse_at("a required number 4 years..year", 2020).is_err());
+        let error = Schema::parse_at("eyr required number 4 year..year+9223372036854775807", 2020).err().unwrap();
+        assert_eq!(error.to_string(), "Schema line 1: 'year..year+9223372036854775807' is not a range like 1920..2002 or year..year+10");
+        assert!(Schema::parse_at("eyr required number 4 year-9223372036854775807..year", -2).is_err());
+        assert!(current_year() >= 2024);
+    }
+}
@@ -0,0 +1,289 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::schema::Schema;
+use crate::Passport;
+
+/// A four digit year.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct Year(pub u16);
+
+impl FromStr for Year {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.len() != 4 || !s.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(());
+        }
+        s.parse().map(Year).map_err(|_| ())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HeightUnit {
+    Cm,
+    In,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Height {
+    pub value: u16,
+    pub unit: HeightUnit,
+}
+
+impl Height {
+    pub fn in_cm(&self) -> f64 {
+        match self.unit {
+            HeightUnit::Cm => self.value as f64,
+            HeightUnit::In => self.value as f64 * 2.54,
+        }
+    }
+}
+
+impl FromStr for Height {
+    type Err = ();
+
+    /// Reads a whole number of `cm` or `in`.  The schema decides the range.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (number, unit) = match (s.strip_suffix("cm"), s.strip_suffix("in")) {
+            (Some(number), _) => (number, HeightUnit::Cm),
+            (_, Some(number)) => (number, HeightUnit::In),
+            _ => return Err(()),
+        };
+        if number.is_empty() || !number.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(());
+        }
+        number.parse().map(|value| Height { value, unit }).map_err(|_| ())
+    }
+}
+
+/// A hair colour written `#rrggbb` in lowercase hex.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HairColor {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+}
+
+impl FromStr for HairColor {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = s.strip_prefix('#').ok_or(())?;
+        if hex.len() != 6 || !hex.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b)) {
+            return Err(());
+        }
+        let channel = |idx: usize| u8::from_str_radix(&hex[idx..idx + 2], 16).map_err(|_| ());
+        Ok(HairColor { red: channel(0)?, green: channel(2)?, blue: channel(4)? })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EyeColor {
+    Amber,
+    Blue,
+    Brown,
+    Gray,
+    Green,
+    Hazel,
+    Other,
+}
+
+impl EyeColor {
+    fn code(&self) -> &'static str {
+        match self {
+            EyeColor::Amber => "amb",
+            EyeColor::Blue => "blu",
+            EyeColor::Brown => "brn",
+            EyeColor::Gray => "gry",
+            EyeColor::Green => "grn",
+            EyeColor::Hazel => "hzl",
+            EyeColor::Other => "oth",
+        }
+    }
+}
+
+impl FromStr for EyeColor {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "amb" => Ok(EyeColor::Amber),
+            "blu" => Ok(EyeColor::Blue),
+            "brn" => Ok(EyeColor::Brown),
+            "gry" => Ok(EyeColor::Gray),
+            "grn" => Ok(EyeColor::Green),
+            "hzl" => Ok(EyeColor::Hazel),
+            "oth" => Ok(EyeColor::Other),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Exactly nine digits, leading zeros included.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PassportId(String);
+
+impl PassportId {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for PassportId {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.len() == 9 && s.bytes().all(|b| b.is_ascii_digit()) {
+            Ok(PassportId(s.to_string()))
+        } else {
+            Err(())
+        }
+    }
+}
+
+/// A passport whose fields have all been checked against a schema's rules
+/// and parsed into their own types.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ValidPassport {
+    pub birth_year: Year,
+    pub issue_year: Year,
+    pub expiration_year: Year,
+    pub height: Height,
+    pub hair_color: HairColor,
+    pub eye_color: EyeColor,
+    pub passport_id: PassportId,
+    pub country_id: Option<String>,
+}
+
+impl ValidPassport {
+    /// The fields a schema needs for passports to be typed.  `cid` is
+    /// optional and may be left out of the schema.
+    pub const FIELDS: [&'static str; 7] = ["byr", "iyr", "eyr", "hgt", "hcl", "ecl", "pid"];
+}
+
+/// Why a passport could not become a `ValidPassport`.
+#[derive(Debug, PartialEq)]
+pub enum PassportError {
+    /// The schema has no rule for the field, so its value can't be checked.
+    NotInSchema(&'static str),
+    Missing(&'static str),
+    Invalid { field: &'static str, value: String },
+}
+
+impl fmt::Display for PassportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PassportError::NotInSchema(field) => write!(f, "{} is not in the schema", field),
+            PassportError::Missing(field) => write!(f, "{} is missing", field),
+            PassportError::Invalid { field, value } => write!(f, "{} '{}' is invalid", field, value),
+        }
+    }
+}
+
+impl TryFrom<(&Passport, &Schema)> for ValidPassport {
+    type Error = PassportError;
+
+    /// Checks each field against the schema's rule, so ranges follow the
+    /// chosen profile and year, then parses it into its type.
+    fn try_from((passport, schema): (&Passport, &Schema)) -> Result<Self, Self::Error> {
+        let field = |name: &'static str| -> Result<&str, PassportError> {
+            let rule = &schema.field(name).ok_or(PassportError::NotInSchema(name))?.rule;
+            match passport.get(name).trim() {
+                "" => Err(PassportError::Missing(name)),
+                value if rule.check(value) => Ok(value),
+                value => Err(PassportError::Invalid { field: name, value: value.to_string() }),
+            }
+        };
+        fn typed<T: FromStr>(name: &'static str, value: &str) -> Result<T, PassportError> {
+            value.parse().map_err(|_| PassportError::Invalid { field: name, value: value.to_string() })
+        }
+
+        Ok(ValidPassport {
+            birth_year: typed("byr", field("byr")?)?,
+            issue_year: typed("iyr", field("iyr")?)?,
+            expiration_year: typed("eyr", field("eyr")?)?,
+            height: typed("hgt", field("hgt")?)?,
+            hair_color: typed("hcl", field("hcl")?)?,
+            eye_color: typed("ecl", field("ecl")?)?,
+            passport_id: typed("pid", field("pid")?)?,
+            // Only fields the schema knows are kept, so without a `cid` rule there is none.
+            country_id: match (passport.get("cid").trim(), schema.field("cid")) {
+                ("", Some(cid)) if cid.required => return Err(PassportError::Missing("cid")),
+                ("", _) => None,
+                _ => Some(field("cid")?.to_string()),
+            },
+        })
+    }
+}
+
+impl fmt::Display for ValidPassport {
+    /// Writes the passport back in the batch file's `key:value` form.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let unit = match self.height.unit {
+            HeightUnit::Cm => "cm",
+            HeightUnit::In => "in",
+        };
+        write!(f, "byr:{} iyr:{} eyr:{} hgt:{}{} hcl:#{:02x}{:02x}{:02x} ecl:{} pid:{}",
+            self.birth_year.0, self.issue_year.0, self.expiration_year.0, self.height.value, unit,
+            self.hair_color.red, self.hair_color.green, self.hair_color.blue,
+            self.eye_color.code(), self.passport_id.as_str())?;
+        if let Some(cid) = &self.country_id {
+            write!(f, " cid:{}", cid)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::Schema;
+    use crate::tests::passport;
+
+    #[test]
+    fn check_typed_passport() {
+        let schema = Schema::profile("north-pole", 2020).unwrap();
+        let p = passport(&schema, "ecl:gry pid:060033327 eyr:2020 hcl:#fffffd byr:1937 iyr:2017 cid:147 hgt:74in");
+        let valid = ValidPassport::try_from((&p, &schema)).unwrap();
+        assert_eq!(valid.birth_year, Year(1937));
+        assert_eq!(valid.height, Height { value: 74, unit: HeightUnit::In });
+        assert!((valid.height.in_cm() - 187.96).abs() < 1e-9);
+        assert_eq!(valid.hair_color, HairColor { red: 0xff, green: 0xff, blue: 0xfd });
+        assert_eq!(valid.eye_color, EyeColor::Gray);
+        assert_eq!(valid.passport_id.as_str(), "060033327");
+        assert_eq!(valid.country_id.as_deref(), Some("147"));
+        assert_eq!(valid.to_string(), "byr:1937 iyr:2017 eyr:2020 hgt:74in hcl:#fffffd ecl:gry pid:060033327 cid:147");
+
+        let without_cid = passport(&schema, "ecl:gry pid:060033327 eyr:2020 hcl:#fffffd byr:1937 iyr:2017 hgt:183cm");
+        assert_eq!(ValidPassport::try_from((&without_cid, &schema)).unwrap().country_id, None);
+
+        let failures = [
+            ("ecl:gry pid:060033327 eyr:2020 hcl:#fffffd iyr:2017 hgt:183cm", PassportError::Missing("byr")),
+            ("ecl:gry pid:060033327 eyr:2020 hcl:#fffffd byr:2003 iyr:2017 hgt:183cm", PassportError::Invalid { field: "byr", value: String::from("2003") }),
+            ("ecl:gry pid:060033327 eyr:2020 hcl:#fffffd byr:1937 iyr:2017 hgt:183", PassportError::Invalid { field: "hgt", value: String::from("183") }),
+            ("ecl:gry pid:060033327 eyr:2020 hcl:#fffffd byr:1937 iyr:2017 hgt:+60in", PassportError::Invalid { field: "hgt", value: String::from("+60in") }),
+            ("ecl:gry pid:060033327 eyr:2020 hcl:#123abcdef byr:1937 iyr:2017 hgt:183cm", PassportError::Invalid { field: "hcl", value: String::from("#123abcdef") }),
+            ("ecl:grey pid:060033327 eyr:2020 hcl:#fffffd byr:1937 iyr:2017 hgt:183cm", PassportError::Invalid { field: "ecl", value: String::from("grey") }),
+            ("ecl:gry pid:+60033327 eyr:2020 hcl:#fffffd byr:1937 iyr:2017 hgt:183cm", PassportError::Invalid { field: "pid", value: String::from("+60033327") }),
+        ];
+        for (text, expected) in failures.iter() {
+            assert_eq!(&ValidPassport::try_from((&passport(&schema, text), &schema)).unwrap_err(), expected, "{}", text);
+        }
+
+        // The ranges come from the schema, so they follow the profile and year.
+        let travel = Schema::profile("travel", 2026).unwrap();
+        let text = "ecl:gry pid:060033327 eyr:2035 hcl:#fffffd byr:1937 iyr:2017 cid:147 hgt:74in";
+        assert_eq!(ValidPassport::try_from((&passport(&travel, text), &travel)).unwrap().expiration_year, Year(2035));
+        assert_eq!(ValidPassport::try_from((&passport(&schema, text), &schema)).unwrap_err(),
+            PassportError::Invalid { field: "eyr", value: String::from("2035") });
+        assert!(ValidPassport::try_from((&passport(&travel, text.replace("iyr:2017", "iyr:2015").as_str()), &travel)).is_err());
+        assert_eq!(ValidPassport::try_from((&passport(&travel, text.replace(" cid:147", "").as_str()), &travel)).unwrap_err(),
+            PassportError::Missing("cid"));
+
+        let narrow = Schema::parse_at("byr required number 4 1950..1960\npid required number 9 0..999999999\n", 2020).unwrap();
+        assert_eq!(ValidPassport::try_from((&passport(&narrow, text), &narrow)).unwrap_err(), PassportError::Invalid { field: "byr", value: String::from("1937") });
+        assert_eq!(ValidPassport::try_from((&passport(&narrow, "byr:1955 pid:060033327"), &narrow)).unwrap_err(), PassportError::NotInSchema("iyr"));
+    }
+}